@@ -1,104 +1,537 @@
+pub mod format;
+pub mod g711;
+mod telephone;
+
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
 use alsa::Direction;
-use alsa::pcm::{Access, Format, HwParams, PCM};
+use alsa::pcm::{Access, Format, Frames, HwParams, IoFormat, State, PCM};
 use alsa::ValueOr;
 use hound::{self, WavWriter};
 
-use crate::{BIT_DEPTH, BUFFER_SIZE, SAMPLE_RATE};
+use crate::{error::Result, resampler::{Quality, Resampler}, DEFAULT_SAMPLE_RATE};
+
+use format::{Dither, Quantizer, SampleFormat};
+use g711::Law;
+use telephone::{TelephoneBand, TELEPHONE_RATE};
+
+// returns the config the device settled on, its rate and sizes may not be the ones asked for
+fn set_pcm_params(pcm: &alsa::PCM, config: OutputConfig) -> Result<OutputConfig> {
+    let hwp = HwParams::any(pcm)?;
+    hwp.set_channels(config.channels.count() as u32)?;
+    hwp.set_rate(config.sample_rate, ValueOr::Nearest)?;
+    hwp.set_format(match config.format {
+        SampleFormat::U8 => Format::U8,
+        SampleFormat::I16 => Format::s16(),
+        SampleFormat::I24 => Format::s32(),       // 24 bit samples in the top of a 32 bit word
+        SampleFormat::F32 => Format::float(),
+    })?;
+    hwp.set_access(Access::RWInterleaved)?;
+    hwp.set_buffer_size_near(config.buffer_size.max(2) as Frames)?;
+    hwp.set_period_size_near(config.period_size.max(1) as Frames, ValueOr::Nearest)?;
+    pcm.hw_params(&hwp)?;
+
+    let hwp = pcm.hw_params_current()?;
+    let negotiated = OutputConfig {
+        sample_rate: hwp.get_rate()?,
+        period_size: hwp.get_period_size()? as usize,
+        buffer_size: hwp.get_buffer_size()? as usize,
+        ..config
+    };
+
+    // playback starts once the buffer is full, so the first periods don't underrun,
+    // and writes wake up whenever a period's worth of room is free
+    let swp = pcm.sw_params_current()?;
+    swp.set_start_threshold(negotiated.buffer_size as Frames)?;
+    swp.set_avail_min(negotiated.period_size as Frames)?;
+    pcm.sw_params(&swp)?;
+    Ok(negotiated)
+}
+
+// samples quantized to the sound card's format, kept between writes so converting doesn't allocate
+enum Converted {
+    U8(Vec<u8>),
+    I16(Vec<i16>),
+    I24(Vec<i32>),
+    F32,            // written as they are
+}
+
+// a sound card output that picks itself back up after underruns
+pub struct PcmWriter {
+    pcm: PCM,
+    channels: usize,
+    xruns: usize,
+    converted: Converted,
+}
+
+impl PcmWriter {
+    fn new(pcm: PCM, channels: Channels, format: SampleFormat) -> Self {
+        let converted = match format {
+            SampleFormat::U8 => Converted::U8(vec![]),
+            SampleFormat::I16 => Converted::I16(vec![]),
+            SampleFormat::I24 => Converted::I24(vec![]),
+            SampleFormat::F32 => Converted::F32,
+        };
+        Self {
+            pcm,
+            channels: channels.count() as usize,
+            xruns: 0,
+            converted,
+        }
+    }
+
+    // room for `samples` converted samples, anything bigger still makes the buffer grow
+    fn reserve(&mut self, samples: usize) {
+        match &mut self.converted {
+            Converted::U8(converted) => converted.reserve(samples),
+            Converted::I16(converted) => converted.reserve(samples),
+            Converted::I24(converted) => converted.reserve(samples),
+            Converted::F32 => (),
+        }
+    }
+
+    pub fn pcm(&self) -> &PCM {
+        &self.pcm
+    }
+
+    // underruns and suspends recovered from so far
+    pub fn xruns(&self) -> usize {
+        self.xruns
+    }
+
+    fn write_quantized(&mut self, buffer: &[f32], quantizer: &mut Quantizer) -> Result<()> {
+        let mut converted = std::mem::replace(&mut self.converted, Converted::F32);
+        let result = match &mut converted {
+            Converted::U8(samples) => {
+                samples.clear();
+                samples.extend(buffer.iter().map(|&sample| (quantizer.quantize(sample) + 128) as u8));
+                self.write(samples)
+            }
+            Converted::I16(samples) => {
+                samples.clear();
+                samples.extend(buffer.iter().map(|&sample| quantizer.quantize(sample) as i16));
+                self.write(samples)
+            }
+            Converted::I24(samples) => {
+                samples.clear();
+                samples.extend(buffer.iter().map(|&sample| quantizer.quantize(sample) << 8));
+                self.write(samples)
+            }
+            Converted::F32 => self.write(buffer),
+        };
+        self.converted = converted;
+        result
+    }
+
+    // blocks until every frame is queued, the device may take fewer than offered per call
+    fn write<S: IoFormat>(&mut self, samples: &[S]) -> Result<()> {
+        let io = self.pcm.io_checked::<S>()?;
+        let mut written = 0;
+        while written < samples.len() {
+            match io.writei(&samples[written..]) {
+                Ok(frames) => written += frames * self.channels,
+                Err(err) => {
+                    // interrupted writes are retried without counting, errors other than these are passed on
+                    if matches!(self.pcm.state(), State::XRun | State::Suspended) {
+                        self.xruns += 1;
+                    }
+                    self.pcm.try_recover(err, true)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn create_wav(path: &Path, channels: Channels, format: SampleFormat, sample_rate: u32) -> Result<WavWriter<BufWriter<File>>> {
+    let spec = hound::WavSpec {
+        channels: channels.count(),
+        sample_rate,
+        bits_per_sample: format.bits(),
+        sample_format: if format.is_float() { hound::SampleFormat::Float } else { hound::SampleFormat::Int },
+    };
+    Ok(hound::WavWriter::create(path, spec)?)
+}
+
+// G.711 encoded 8 kHz telephone audio, either as a WAV file (format tag 6/7) or a raw .al/.ul stream
+pub struct G711Writer {
+    file: BufWriter<File>,
+    law: Law,
+    band: TelephoneBand,
+    resampler: Option<Box<Resampler>>,  // straight to 8 kHz for rates that aren't a multiple of it
+    resampled: Vec<f32>,
+    header: bool,
+    data_len: u32,
+}
+
+impl G711Writer {
+    fn new(file_name: &str, law: Law, header: bool, sample_rate: u32) -> Result<Self> {
+        let file = File::create(file_name)?;
+        let (band, resampler) = if sample_rate.is_multiple_of(TELEPHONE_RATE) {
+            (TelephoneBand::new(sample_rate), None)
+        }
+        else {
+            (TelephoneBand::new(TELEPHONE_RATE), Some(Box::new(Resampler::new(sample_rate, TELEPHONE_RATE, Quality::Medium, 1)?)))
+        };
+        let mut writer = Self {
+            file: BufWriter::new(file),
+            law,
+            band,
+            resampler,
+            resampled: vec![],
+            header,
+            data_len: 0,
+        };
+        if header {
+            writer.write_header()?;
+        }
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let data_len = self.data_len;
+        let file = &mut self.file;
+
+        // non-pcm formats need the cbSize field and a fact chunk
+        file.write_all(b"RIFF")?;
+        file.write_all(&(4 + 26 + 12 + 8 + data_len + data_len % 2).to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+
+        file.write_all(b"fmt ")?;
+        file.write_all(&18u32.to_le_bytes())?;
+        file.write_all(&self.law.format_tag().to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?;                 // channels
+        file.write_all(&TELEPHONE_RATE.to_le_bytes())?;       // sample rate
+        file.write_all(&TELEPHONE_RATE.to_le_bytes())?;       // byte rate
+        file.write_all(&1u16.to_le_bytes())?;                 // block align
+        file.write_all(&8u16.to_le_bytes())?;                 // bits per sample
+        file.write_all(&0u16.to_le_bytes())?;                 // cbSize
+
+        file.write_all(b"fact")?;
+        file.write_all(&4u32.to_le_bytes())?;
+        file.write_all(&data_len.to_le_bytes())?;
 
-fn set_pcm_params(pcm: &alsa::PCM) {
-    let hwp = HwParams::any(&pcm).unwrap();
-    hwp.set_channels(1).unwrap();
-    hwp.set_rate(SAMPLE_RATE, ValueOr::Nearest).unwrap();
-    hwp.set_format(Format::U8).unwrap();
-    hwp.set_access(Access::RWInterleaved).unwrap();
-    pcm.hw_params(&hwp).unwrap();
+        file.write_all(b"data")?;
+        file.write_all(&data_len.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn write(&mut self, buffer: &[f32]) -> Result<()> {
+        match self.resampler {
+            Some(ref mut resampler) => {
+                let mut resampled = std::mem::take(&mut self.resampled);
+                resampler.process(buffer, &mut resampled);
+                self.encode(&resampled)?;
+                resampled.clear();
+                self.resampled = resampled;
+                Ok(())
+            }
+            None => self.encode(buffer),
+        }
+    }
+
+    fn encode(&mut self, buffer: &[f32]) -> Result<()> {
+        for &sample in buffer {
+            if let Some(sample) = self.band.process(sample) {
+                let linear = (sample * 32768.).round().clamp(-32768., 32767.) as i16;
+                self.file.write_all(&[self.law.encode(linear)])?;
+                self.data_len += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // patches the header with the final length, nothing can be written after this
+    fn finalize(&mut self) -> Result<()> {
+        if let Some(mut resampler) = self.resampler.take() {
+            let mut resampled = vec![];
+            resampler.flush(&mut resampled);
+            self.encode(&resampled)?;
+        }
+        if self.header {
+            // riff chunks are padded to an even length
+            if self.data_len % 2 == 1 {
+                self.file.write_all(&[0])?;
+            }
+            self.file.seek(SeekFrom::Start(0))?;
+            self.write_header()?;
+            self.file.seek(SeekFrom::End(0))?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
 }
 
 pub enum Writer {
-    PCM(PCM),
+    PCM(PcmWriter),
     WAV(WavWriter<BufWriter<File>>),
+    G711(G711Writer),
 }
 
 impl Writer {
-    fn new(mode: AudioMode) -> Self {
-        match mode {
+    // with the config actually used
+    fn new(mode: AudioMode, config: OutputConfig) -> Result<(Self, OutputConfig)> {
+        let OutputConfig { channels, format, sample_rate, .. } = config;
+        let writer = match mode {
             AudioMode::Play => {
-                let pcm = PCM::new("default", Direction::Playback, false).unwrap();
-                set_pcm_params(&pcm);
+                let pcm = PCM::new("default", Direction::Playback, false)?;
+                let config = set_pcm_params(&pcm, config)?;
 
-                Self::PCM(pcm)
+                return Ok((Self::PCM(PcmWriter::new(pcm, channels, config.format)), config));
             }
             AudioMode::Record(file_name) => {
-                let spec = hound::WavSpec {
-                    channels: 1,
-                    sample_rate: SAMPLE_RATE,
-                    bits_per_sample: BIT_DEPTH,
-                    sample_format: hound::SampleFormat::Int,
-                };
                 let file_name = "wav/".to_string() + &file_name + ".wav";
-                let writer = hound::WavWriter::create(file_name, spec).unwrap();
-        
-                Self::WAV(writer)
+                Self::WAV(create_wav(Path::new(&file_name), channels, format, sample_rate)?)
             }
-        }
+            AudioMode::File(path) => Self::WAV(create_wav(&path, channels, format, sample_rate)?),
+            AudioMode::Telephone(file_name, law) => {
+                let file_name = "wav/".to_string() + &file_name + ".wav";
+                Self::G711(G711Writer::new(&file_name, law, true, sample_rate)?)
+            }
+            AudioMode::TelephoneRaw(file_name, law) => {
+                let file_name = "wav/".to_string() + &file_name + "." + law.extension();
+                Self::G711(G711Writer::new(&file_name, law, false, sample_rate)?)
+            }
+        };
+        Ok((writer, config))
     }
 
-    fn write(&mut self, buffer: &Vec<u8>) {
+    fn write(&mut self, buffer: &[f32], quantizer: &mut Quantizer) -> Result<()> {
         match self {
-            Self::PCM(pcm) => {
-                let io = pcm.io_u8().unwrap();
-                io.writei(buffer).unwrap();
-            }
+            Self::PCM(writer) => writer.write_quantized(buffer, quantizer)?,
             Self::WAV(writer) => {
                 for &sample in buffer {
-                    writer.write_sample((sample as i16 - 128) as i8).unwrap();
+                    match quantizer.format() {
+                        SampleFormat::U8 => writer.write_sample(quantizer.quantize(sample) as i8)?,
+                        SampleFormat::I16 => writer.write_sample(quantizer.quantize(sample) as i16)?,
+                        SampleFormat::I24 => writer.write_sample(quantizer.quantize(sample))?,
+                        SampleFormat::F32 => writer.write_sample(sample)?,
+                    }
                 }
             }
+            Self::G711(writer) => writer.write(buffer)?,
         }
+        Ok(())
     }
 
-    fn drain(&mut self) {
+    fn drain(&mut self) -> Result<()> {
         match self {
-            Self::PCM(pcm) => pcm.drain().unwrap(),
-            Self::WAV(_writer) => {}
+            Self::PCM(writer) => writer.pcm.drain()?,
+            Self::WAV(writer) => writer.flush()?,
+            Self::G711(writer) => writer.finalize()?,
         }
+        Ok(())
     }
 }
 
 pub enum AudioMode {
     Play,
-    Record(String),
+    Record(String),     // name of a wav file in wav/
+    File(PathBuf),      // wav file anywhere
+    Telephone(String, Law),
+    TelephoneRaw(String, Law),
+}
+
+impl AudioMode {
+    // telephone audio is always mono
+    fn is_telephone(&self) -> bool {
+        matches!(self, Self::Telephone(..) | Self::TelephoneRaw(..))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Channels {
+    #[default]
+    Mono,
+    Stereo,     // samples are sent interleaved, left first
+}
+
+impl Channels {
+    pub fn count(self) -> u16 {
+        match self {
+            Self::Mono => 1,
+            Self::Stereo => 2,
+        }
+    }
+}
+
+const DEFAULT_PERIOD_SIZE: usize = 256;
+const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+// everything about the output besides where it goes
+#[derive(Clone, Copy, Debug)]
+pub struct OutputConfig {
+    pub channels: Channels,
+    pub format: SampleFormat,
+    pub dither: Dither,
+    pub sample_rate: u32,       // a request, the sound card may pick the nearest rate it supports
+    pub render_rate: Option<u32>,   // when the synth should run at another rate and be resampled to the output's
+    pub quality: Quality,       // of that resampling
+    pub period_size: usize,     // frames the sound card takes at a time, and the block the engine renders
+    pub buffer_size: usize,     // frames queued on the sound card, more survives slower renders but adds latency
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            channels: Channels::default(),
+            format: SampleFormat::default(),
+            dither: Dither::default(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            render_rate: None,
+            quality: Quality::default(),
+            period_size: DEFAULT_PERIOD_SIZE,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
 }
 
 pub struct AudioOut {
     writer: Writer,
-    buffer: Vec<u8>
+    buffer: Vec<f32>,
+    channels: Channels,
+    quantizer: Quantizer,
+    resampler: Option<Resampler>,
+    resampled: Vec<f32>,
+    sample_rate: u32,           // of the samples sent
+    output_rate: u32,           // of the writer
+    period_size: usize,
+    buffer_size: usize,
 }
 
 impl AudioOut {
 
-    pub fn new(mode: AudioMode) -> Self {
-        let writer = Writer::new(mode);
-        let buffer = vec![];
+    pub fn new(mode: AudioMode) -> Result<Self> {
+        Self::with_channels(mode, Channels::Mono)
+    }
 
-        Self {
+    pub fn with_channels(mode: AudioMode, channels: Channels) -> Result<Self> {
+        Self::with_config(mode, OutputConfig { channels, ..Default::default() })
+    }
+
+    pub fn with_format(mode: AudioMode, channels: Channels, format: SampleFormat, dither: Dither) -> Result<Self> {
+        Self::with_config(mode, OutputConfig { channels, format, dither, ..Default::default() })
+    }
+
+    // telephone modes encode the float samples themselves and ignore the format
+    pub fn with_config(mode: AudioMode, mut config: OutputConfig) -> Result<Self> {
+        if config.sample_rate == 0 || config.render_rate == Some(0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "sample rates must be above 0 Hz").into());
+        }
+        if mode.is_telephone() {
+            config.channels = Channels::Mono;
+        }
+        let (mut writer, config) = Writer::new(mode, config)?;
+        let output_rate = config.sample_rate;
+        let sample_rate = config.render_rate.unwrap_or(output_rate);
+        let resampler = if sample_rate != output_rate {
+            Some(Resampler::new(sample_rate, output_rate, config.quality, config.channels.count() as usize)?)
+        }
+        else {
+            None
+        };
+
+        // a period's worth of room all the way to the card, so writing doesn't allocate once playing
+        let samples_per_frame = config.channels.count() as usize;
+        let buffer = Vec::with_capacity(config.period_size * samples_per_frame);
+        let output_frames = (config.period_size as u64 * output_rate as u64).div_ceil(sample_rate as u64) as usize + 1;
+        let resampled = Vec::with_capacity(if resampler.is_some() { output_frames * samples_per_frame } else { 0 });
+        if let Writer::PCM(ref mut writer) = writer {
+            writer.reserve(output_frames * samples_per_frame);
+        }
+
+        Ok(Self {
             writer,
             buffer,
+            channels: config.channels,
+            quantizer: Quantizer::new(config.format, config.dither, config.channels),
+            resampler,
+            resampled,
+            sample_rate,
+            output_rate,
+            period_size: config.period_size,
+            buffer_size: config.buffer_size,
+        })
+    }
+
+    pub fn channels(&self) -> Channels {
+        self.channels
+    }
+
+    // what the synth has to render at, after negotiating with the sound card
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // what's written, differs from the sample rate when resampling
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.quantizer.format()
+    }
+
+    // in frames, as negotiated with the sound card
+    pub fn period_size(&self) -> usize {
+        self.period_size
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    // underruns the sound card has been recovered from, always 0 for files
+    pub fn xruns(&self) -> usize {
+        match &self.writer {
+            Writer::PCM(writer) => writer.xruns(),
+            Writer::WAV(_) | Writer::G711(_) => 0,
         }
     }
 
-    pub fn send(&mut self, sample: u8) {
+    // sample from -1 to 1
+    pub fn send(&mut self, sample: f32) -> Result<()> {
         self.buffer.push(sample);
-        if self.buffer.len() >= BUFFER_SIZE {
-            self.writer.write(&self.buffer);
-            self.buffer.clear();
+        if self.buffer.len() >= self.period_size * self.channels.count() as usize {
+            self.write_buffer()?;
+        }
+        Ok(())
+    }
+
+    // interleaved samples, written out a period at a time
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        self.buffer.extend_from_slice(samples);
+        if self.buffer.len() >= self.period_size * self.channels.count() as usize {
+            self.write_buffer()?;
         }
+        Ok(())
     }
 
-    pub fn drain(&mut self) {
-        self.writer.drain();
+    fn write_buffer(&mut self) -> Result<()> {
+        match self.resampler {
+            Some(ref mut resampler) => {
+                resampler.process(&self.buffer, &mut self.resampled);
+                self.writer.write(&self.resampled, &mut self.quantizer)?;
+                self.resampled.clear();
+            }
+            None => self.writer.write(&self.buffer, &mut self.quantizer)?,
+        }
+        self.buffer.clear();
+        Ok(())
+    }
+
+    // writes out everything sent and finishes the file, telephone files have no valid header until this is called
+    pub fn drain(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_buffer()?;
+        }
+        if let Some(ref mut resampler) = self.resampler {
+            resampler.flush(&mut self.resampled);
+            self.writer.write(&self.resampled, &mut self.quantizer)?;
+            self.resampled.clear();
+        }
+        self.writer.drain()
     }
 }
\ No newline at end of file
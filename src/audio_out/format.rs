@@ -0,0 +1,85 @@
+use crate::audio_out::Channels;
+
+const SHAPING: [f32; 2] = [2., -1.];    // error feedback for a (1 - z^-1)^2 noise transfer, +12 dB at nyquist
+const MAX_ERROR: f32 = 2.;              // in steps, keeps the shaping loop stable when the output clips
+
+// how samples are stored by the writer, the engine itself works in float
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleFormat {
+    U8,
+    #[default]
+    I16,
+    I24,
+    F32,
+}
+
+impl SampleFormat {
+    pub fn bits(self) -> u16 {
+        match self {
+            Self::U8 => 8,
+            Self::I16 => 16,
+            Self::I24 => 24,
+            Self::F32 => 32,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        self == Self::F32
+    }
+}
+
+// noise added before rounding to an integer format, worth it at 8 bits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    #[default]
+    None,
+    Triangular,     // tpdf, trades the quantization distortion for a steady noise floor
+    Shaped,         // tpdf with the noise pushed up towards nyquist, where it's harder to hear
+}
+
+// rounds float samples to the format's bit depth, interleaved channels keep their own error history
+pub struct Quantizer {
+    format: SampleFormat,
+    dither: Dither,
+    errors: Vec<[f32; 2]>,
+    channel: usize,
+}
+
+impl Quantizer {
+    pub fn new(format: SampleFormat, dither: Dither, channels: Channels) -> Self {
+        Self {
+            format,
+            dither,
+            errors: vec![[0.; 2]; channels.count() as usize],
+            channel: 0,
+        }
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    pub fn dither(&self) -> Dither {
+        self.dither
+    }
+
+    // signed, in steps of the format's bit depth, float formats shouldn't be quantized
+    pub fn quantize(&mut self, sample: f32) -> i32 {
+        let scale = (1u32 << (self.format.bits() - 1)) as f32;
+        let channel = self.channel;
+        self.channel = (channel + 1) % self.errors.len();
+        let errors = &mut self.errors[channel];
+
+        let mut value = sample * scale;
+        if self.dither == Dither::Shaped {
+            value -= SHAPING[0] * errors[0] + SHAPING[1] * errors[1];
+        }
+        let noise = match self.dither {
+            Dither::None => 0.,
+            Dither::Triangular | Dither::Shaped => rand::random::<f32>() - rand::random::<f32>(),
+        };
+        let quantized = (value + noise).round().clamp(-scale, scale - 1.);
+        *errors = [(quantized - value).clamp(-MAX_ERROR, MAX_ERROR), errors[0]];
+        quantized as i32
+    }
+}
@@ -0,0 +1,143 @@
+// G.711 companding, ported from the Sun Microsystems reference implementation (g711.c)
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Law {
+    ALaw,
+    MuLaw,
+}
+
+impl Law {
+    // WAVE_FORMAT_ALAW and WAVE_FORMAT_MULAW
+    pub fn format_tag(self) -> u16 {
+        match self {
+            Self::ALaw => 6,
+            Self::MuLaw => 7,
+        }
+    }
+
+    // extension used by raw headerless streams
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::ALaw => "al",
+            Self::MuLaw => "ul",
+        }
+    }
+
+    pub fn encode(self, sample: i16) -> u8 {
+        match self {
+            Self::ALaw => linear2alaw(sample),
+            Self::MuLaw => linear2ulaw(sample),
+        }
+    }
+
+    pub fn decode(self, code: u8) -> i16 {
+        match self {
+            Self::ALaw => alaw2linear(code),
+            Self::MuLaw => ulaw2linear(code),
+        }
+    }
+}
+
+const SEGMENT_ENDS_ALAW: [i16; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+const SEGMENT_ENDS_ULAW: [i16; 8] = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+
+const ULAW_BIAS: i16 = 0x84;
+const ULAW_CLIP: i16 = 8159;
+
+fn segment(value: i16, ends: &[i16; 8]) -> usize {
+    ends.iter().position(|&end| value <= end).unwrap_or(8)
+}
+
+pub fn linear2alaw(sample: i16) -> u8 {
+    // a-law works on 13 bit magnitudes
+    let mut value = sample >> 3;
+    let mask = if value >= 0 {
+        0xD5
+    } else {
+        value = -value - 1;
+        0x55
+    };
+
+    let seg = segment(value, &SEGMENT_ENDS_ALAW);
+    if seg >= 8 {
+        return 0x7F ^ mask;
+    }
+
+    let mut code = (seg as u8) << 4;
+    if seg < 2 {
+        code |= ((value >> 1) & 0x0F) as u8;
+    } else {
+        code |= ((value >> seg) & 0x0F) as u8;
+    }
+    code ^ mask
+}
+
+pub fn alaw2linear(code: u8) -> i16 {
+    let code = code ^ 0x55;
+    let seg = (code & 0x70) >> 4;
+    let mut value = ((code & 0x0F) as i16) << 4;
+    match seg {
+        0 => value += 8,
+        1 => value += 0x108,
+        _ => value = (value + 0x108) << (seg - 1),
+    }
+    if code & 0x80 != 0 { value } else { -value }
+}
+
+pub fn linear2ulaw(sample: i16) -> u8 {
+    // mu-law works on 14 bit magnitudes
+    let mut value = sample >> 2;
+    let mask = if value < 0 {
+        value = -value;
+        0x7F
+    } else {
+        0xFF
+    };
+    let value = value.min(ULAW_CLIP) + (ULAW_BIAS >> 2);
+
+    let seg = segment(value, &SEGMENT_ENDS_ULAW);
+    if seg >= 8 {
+        return 0x7F ^ mask;
+    }
+
+    let code = ((seg as u8) << 4) | ((value >> (seg + 1)) & 0x0F) as u8;
+    code ^ mask
+}
+
+pub fn ulaw2linear(code: u8) -> i16 {
+    let code = !code;
+    let mut value = (((code & 0x0F) as i16) << 3) + ULAW_BIAS;
+    value <<= (code & 0x70) >> 4;
+    if code & 0x80 != 0 { ULAW_BIAS - value } else { value - ULAW_BIAS }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    // signal to quantization noise ratio of a full scale sine sent through the codec and back
+    fn round_trip_snr(law: Law) -> f64 {
+        let (mut signal, mut noise) = (0., 0.);
+        for n in 0..8000 {
+            let sample = (32767. * (2. * PI * 1011. * n as f64 / 8000.).sin()).round() as i16;
+            let decoded = law.decode(law.encode(sample));
+            signal += (sample as f64).powi(2);
+            noise += (sample as f64 - decoded as f64).powi(2);
+        }
+        10. * (signal / noise).log10()
+    }
+
+    #[test]
+    fn alaw_round_trip_snr() {
+        let snr = round_trip_snr(Law::ALaw);
+        assert!(snr >= 37., "a-law snr {snr:.1} dB");
+    }
+
+    #[test]
+    fn mulaw_round_trip_snr() {
+        let snr = round_trip_snr(Law::MuLaw);
+        assert!(snr >= 37., "mu-law snr {snr:.1} dB");
+    }
+}
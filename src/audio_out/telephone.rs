@@ -0,0 +1,112 @@
+use std::f32::consts::PI;
+
+pub const TELEPHONE_RATE: u32 = 8000;
+const BAND_LOW: f32 = 300.;
+const BAND_HIGH: f32 = 3400.;
+const TAPS_PER_PHASE: usize = 40;
+
+// second order section, transposed direct form II
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    fn highpass(cutoff: f32, q: f32, sample_rate: u32) -> Self {
+        let w0 = 2. * PI * cutoff / sample_rate as f32;
+        let alpha = w0.sin() / (2. * q);
+        let cos = w0.cos();
+        let a0 = 1. + alpha;
+        Self {
+            b0: (1. + cos) / 2. / a0,
+            b1: -(1. + cos) / a0,
+            b2: (1. + cos) / 2. / a0,
+            a1: -2. * cos / a0,
+            a2: (1. - alpha) / a0,
+            z1: 0.,
+            z2: 0.,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+// band limits the engine output to the 300-3400 Hz telephone band and decimates it to 8 kHz
+pub struct TelephoneBand {
+    taps: Vec<f32>,
+    history: Vec<f32>,
+    cursor: usize,
+    factor: usize,
+    phase: usize,
+    highpass: [Biquad; 2],
+}
+
+impl TelephoneBand {
+    // the engine rate has to be a multiple of 8 kHz, so decimation is by an integer factor
+    pub fn new(sample_rate: u32) -> Self {
+        let factor = (sample_rate / TELEPHONE_RATE).max(1) as usize;
+
+        // blackman windowed sinc lowpass, the transition band folds back above 3400 Hz only
+        let len = factor * TAPS_PER_PHASE + 1;
+        let cutoff = BAND_HIGH / sample_rate as f32;
+        let middle = (len / 2) as f32;
+        let mut taps: Vec<f32> = (0..len)
+            .map(|i| {
+                let x = i as f32 - middle;
+                let sinc = if x == 0. { 2. * cutoff } else { (2. * PI * cutoff * x).sin() / (PI * x) };
+                let t = i as f32 / (len - 1) as f32;
+                let window = 0.42 - 0.5 * (2. * PI * t).cos() + 0.08 * (4. * PI * t).cos();
+                sinc * window
+            })
+            .collect();
+        let sum: f32 = taps.iter().sum();
+        taps.iter_mut().for_each(|tap| *tap /= sum);
+
+        // 4th order butterworth highpass at the decimated rate
+        let highpass = [
+            Biquad::highpass(BAND_LOW, 0.5412, TELEPHONE_RATE),
+            Biquad::highpass(BAND_LOW, 1.3066, TELEPHONE_RATE),
+        ];
+
+        Self {
+            history: vec![0.; len],
+            taps,
+            cursor: 0,
+            factor,
+            phase: 0,
+            highpass,
+        }
+    }
+
+    // takes one sample at the engine rate, returns one at 8 kHz every `factor` calls
+    pub fn process(&mut self, sample: f32) -> Option<f32> {
+        let len = self.history.len();
+        self.history[self.cursor] = sample;
+        self.cursor = (self.cursor + 1) % len;
+
+        self.phase += 1;
+        if self.phase < self.factor {
+            return None;
+        }
+        self.phase = 0;
+
+        let mut output = 0.;
+        for (i, tap) in self.taps.iter().enumerate() {
+            output += tap * self.history[(self.cursor + i) % len];
+        }
+        for section in &mut self.highpass {
+            output = section.process(output);
+        }
+        Some(output)
+    }
+}
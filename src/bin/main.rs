@@ -1,32 +1,98 @@
-use std::{env, path::Path};
+use std::{env, path::Path, process};
 
-use duvet::{audio_out::AudioMode, player::Player};
+use duvet::{audio_out::AudioMode, error::Result, player::Player};
 
 
 
 fn main() {
-    
+
     let args: Vec<String> = env::args().collect();
-    let file_path = Path::new(&args[1]);
+    if args.len() < 2 {
+        eprintln!("usage: {} <midi file>", args[0]);
+        process::exit(1);
+    }
+
+    if let Err(err) = run(Path::new(&args[1])) {
+        eprintln!("duvet: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(file_path: &Path) -> Result<()> {
 
-    // play from midi file
-    let mut player = Player::new_midi(file_path, AudioMode::Play);
+    // play from midi file on a separate audio thread, until the last notes have rung out
+    // let (engine, _events) = Engine::play(Synth::new(), AudioOut::new(AudioMode::Play)?, MidiPlayer::new(file_path)?, 64)?;
+    // while engine.is_running() {
+    //     thread::sleep(Duration::from_millis(50));
+    // }
+    // engine.stop()?;
+    // return Ok(());
 
     // write to wav from midi file
-    let file_name = file_path.file_stem().unwrap().to_str().unwrap().to_string();
-    let mut player = Player::new_midi(file_path, AudioMode::Record(file_name));
+    let file_name = file_path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let mut player = Player::new_midi(file_path, AudioMode::Record(file_name))?;
+
+    // write duvet.mid included in the binary to wav, with the instruments it was arranged for
+    // let mut player = Player::new_midi_bytes(include_bytes!("../../midi/duvet.mid"), AudioMode::Record("duvet".to_string()))?;
+    // player.add_song_presets();
+
+    // write stereo wav from midi file, channels placed by their pan controllers
+    // let mut player = Player::with_channels(PlayerKind::Midi(MidiPlayer::new(file_path)?), AudioMode::Record(file_name), Channels::Stereo)?;
 
-    // play from midi included in the binary
-    // let mut player = Player::new_midi(include_bytes!("../../midi/duvet.mid"), AudioMode::Play);
+    // write 8 bit wav from midi file, with noise shaped dither
+    // let out = AudioOut::with_format(AudioMode::Record(file_name), Channels::Mono, SampleFormat::U8, Dither::Shaped)?;
+    // let mut player = Player::with_output(PlayerKind::Midi(MidiPlayer::new(file_path)?), out);
 
-    // play using computer keyboard (not working yet)
-    // let mut player = Player::new_keyboard(AudioMode::Play);
+    // write 96 kHz 24 bit wav from midi file
+    // let config = OutputConfig { format: SampleFormat::I24, sample_rate: 96000, ..Default::default() };
+    // let mut player = Player::with_output(PlayerKind::Midi(MidiPlayer::new(file_path)?), AudioOut::with_config(AudioMode::Record(file_name), config)?);
+
+    // write 44.1 kHz wav from midi file, rendered at 48 kHz and resampled
+    // let config = OutputConfig { sample_rate: 44100, render_rate: Some(48000), ..Default::default() };
+    // let mut player = Player::with_output(PlayerKind::Midi(MidiPlayer::new(file_path)?), AudioOut::with_config(AudioMode::Record(file_name), config)?);
+
+    // render midi file to any path faster than real time, letting the last notes and reverb ring out
+    // let seconds = OfflineRender::new(MidiPlayer::new(file_path)?).render(Path::new("duvet.wav"), OutputConfig::default(), |progress| {
+    //     eprint!("\rrendering {:3.0}%", 100. * progress);
+    // })?;
+    // eprintln!("\rrendered {:.1} s", seconds);
+    // return Ok(());
+
+    // write a-law encoded telephone audio from midi file
+    // let mut player = Player::new_midi(file_path, AudioMode::Telephone(file_name, Law::ALaw))?;
+
+    // play duvet.mid included in the binary, with the instruments it was arranged for
+    // let midi_player = MidiPlayer::from_bytes(include_bytes!("../../midi/duvet.mid"))?;
+    // let mut synth = Synth::new();
+    // song_presets(&mut synth, &midi_player);
+    // let (engine, _events) = Engine::play(synth, AudioOut::new(AudioMode::Play)?, midi_player, 64)?;
+    // while engine.is_running() {
+    //     thread::sleep(Duration::from_millis(50));
+    // }
+    // engine.stop()?;
+    // return Ok(());
+
+    // play midi sent to the "duvet" sequencer port, e.g. after `aconnect <keyboard> duvet`
+    // let mut player = Player::new_input(InputPlayer::sequencer("duvet in")?, AudioMode::Play)?;
+
+    // play using computer keyboard
+    // let mut player = Player::new_keyboard(AudioMode::Play)?;
+
+    // play midi input on a separate audio thread, with a smaller sound card buffer for less latency
+    // let config = OutputConfig { period_size: 128, buffer_size: 512, ..Default::default() };
+    // let (engine, mut events) = Engine::start(Synth::new(), AudioOut::with_config(AudioMode::Play, config)?, 1024)?;
+    // let mut input = MidiInput::sequencer("duvet", "duvet in")?;
+    // while engine.is_running() {
+    //     input.poll(|channel, message| { let _ = events.push(Event::Midi(channel, message)); })?;
+    //     thread::sleep(Duration::from_millis(1));
+    // }
+    // engine.stop()?;
+    // return Ok(());
 
 
     // main update loop
-    while player.update() {
+    while player.update()? {
         // thread::sleep(Duration::from_millis(50));
     }
-    player.drain();
+    player.drain()
 }
-
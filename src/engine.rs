@@ -0,0 +1,178 @@
+use std::{sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc}, thread::{self, JoinHandle}};
+
+use midly::MidiMessage;
+
+use crate::{audio_out::{AudioOut, Channels}, error::Result, player::{MidiPlayer, Tail, DEFAULT_MAX_TAIL}, ring_buffer::{ring_buffer, Consumer, Producer}, synth::Synth};
+
+// what other threads can ask of the synth while it plays
+#[derive(Clone, Copy, Debug)]
+pub enum Event {
+    Midi(u8, MidiMessage),      // channel, message
+    Tempo(f32),                 // bpm, for tempo synced delays
+}
+
+// plays a synth on its own thread, a period at a time, so nothing else running can make the sound card underrun
+// events are picked up at the start of each period, so they land up to a period late
+pub struct Engine {
+    thread: Option<JoinHandle<Result<Synth>>>,
+    running: Arc<AtomicBool>,
+    xruns: Arc<AtomicUsize>,
+    sample_rate: u32,
+    period_size: usize,
+    channels: Channels,
+}
+
+impl Engine {
+    // `queue_size` is how many events can wait between two periods before pushes fail
+    pub fn start(synth: Synth, out: AudioOut, queue_size: usize) -> Result<(Self, Producer<Event>)> {
+        Self::spawn(synth, out, None, queue_size)
+    }
+
+    // plays a file on the audio thread with whatever instruments `synth` was set up with,
+    // stopping by itself once the song's tail has rung out, events pushed meanwhile are played on top of it
+    pub fn play(synth: Synth, out: AudioOut, midi_player: MidiPlayer, queue_size: usize) -> Result<(Self, Producer<Event>)> {
+        Self::spawn(synth, out, Some(midi_player), queue_size)
+    }
+
+    fn spawn(mut synth: Synth, out: AudioOut, midi_player: Option<MidiPlayer>, queue_size: usize) -> Result<(Self, Producer<Event>)> {
+        let (producer, consumer) = ring_buffer(queue_size);
+        let running = Arc::new(AtomicBool::new(true));
+        let xruns = Arc::new(AtomicUsize::new(0));
+
+        let sample_rate = out.sample_rate();
+        let period_size = out.period_size();
+        let channels = out.channels();
+        synth.set_sample_rate(sample_rate);
+
+        let thread = {
+            let running = running.clone();
+            let xruns = xruns.clone();
+            thread::Builder::new()
+                .name("duvet audio".to_string())
+                .spawn(move || {
+                    let result = run(synth, out, midi_player, consumer, &running, &xruns);
+                    running.store(false, Ordering::Release);
+                    result
+                })?
+        };
+
+        let engine = Self {
+            thread: Some(thread),
+            running,
+            xruns,
+            sample_rate,
+            period_size,
+            channels,
+        };
+        Ok((engine, producer))
+    }
+
+    // false once stopped, once a song has played out, or if the output failed
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    // underruns so far, each one an audible gap
+    pub fn xruns(&self) -> usize {
+        self.xruns.load(Ordering::Relaxed)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // frames rendered between two looks at the event queue
+    pub fn period_size(&self) -> usize {
+        self.period_size
+    }
+
+    pub fn channels(&self) -> Channels {
+        self.channels
+    }
+
+    // lets the queued audio play out, then hands the synth back, or the error that stopped the thread
+    pub fn stop(mut self) -> Result<Synth> {
+        self.running.store(false, Ordering::Release);
+        let thread = self.thread.take().expect("engine thread is only joined once");
+        thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// the audio thread, events reach it without locks, but program changes build their instrument and note ons their voice
+// right here, both of which allocate, so a busy allocator can still cost an xrun
+fn run(mut synth: Synth, mut out: AudioOut, mut midi_player: Option<MidiPlayer>, mut events: Consumer<Event>, running: &AtomicBool, xruns: &AtomicUsize) -> Result<Synth> {
+    let sample_rate = out.sample_rate();
+    let channels = out.channels();
+    let period_size = out.period_size();
+    let mut block = vec![0.; period_size * channels.count() as usize];
+    let mut clock: u64 = 0;
+    let mut tail = None;        // once the song's last event has played
+
+    while running.load(Ordering::Acquire) {
+        while let Some(event) = events.pop() {
+            match event {
+                Event::Midi(channel, message) => synth.dispatch(channel, message),
+                Event::Tempo(bpm) => synth.set_tempo(bpm),
+            }
+        }
+
+        match &mut midi_player {
+            Some(midi_player) if tail.is_none() => {
+                if midi_player.render_block(&mut synth, clock, sample_rate, &mut block, channels).is_none() {
+                    tail = Some(Tail::new(sample_rate, DEFAULT_MAX_TAIL));
+                }
+            }
+            _ => synth.render_channels(&mut block, channels),
+        }
+        out.write(&block)?;
+        xruns.store(out.xruns(), Ordering::Relaxed);
+        clock += period_size as u64;
+
+        if let Some(tail) = &mut tail {
+            if !tail.update(&synth, &block, period_size) {
+                break;
+            }
+        }
+    }
+
+    out.drain()?;
+    Ok(synth)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process, thread, time::Duration};
+
+    use super::*;
+    use crate::{audio_out::AudioMode, player::tests::song};
+
+    #[test]
+    fn stops_once_the_song_has_rung_out() {
+        let path = env::temp_dir().join(format!("duvet-engine-{}.wav", process::id()));
+        let out = AudioOut::new(AudioMode::File(path.clone())).unwrap();
+        let sample_rate = out.sample_rate();
+        let midi_player = MidiPlayer::from_bytes(&song(0)).unwrap();
+
+        let (engine, _events) = Engine::play(Synth::new(), out, midi_player, 16).unwrap();
+        while engine.is_running() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let synth = engine.stop().unwrap();
+        assert!(synth.is_idle());
+
+        let frames = hound::WavReader::open(&path).unwrap().duration() as f64;
+        fs::remove_file(&path).unwrap();
+        // the half second note and its release, stopped by the silence rather than the tail cap
+        let seconds = frames / sample_rate as f64;
+        assert!(seconds > 0.5 && seconds < 0.5 + DEFAULT_MAX_TAIL, "{seconds} s");
+    }
+}
@@ -0,0 +1,60 @@
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum DuvetError {
+    Io(io::Error),
+    Midi(midly::Error),
+    AudioDevice(alsa::Error),
+    Encoding(hound::Error),
+}
+
+pub type Result<T> = std::result::Result<T, DuvetError>;
+
+impl fmt::Display for DuvetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "i/o error: {}", err),
+            Self::Midi(err) => write!(f, "midi parse error: {}", err),
+            Self::AudioDevice(err) => write!(f, "audio device error: {}", err),
+            Self::Encoding(err) => write!(f, "encoding error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DuvetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Midi(err) => Some(err),
+            Self::AudioDevice(err) => Some(err),
+            Self::Encoding(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for DuvetError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<midly::Error> for DuvetError {
+    fn from(err: midly::Error) -> Self {
+        Self::Midi(err)
+    }
+}
+
+impl From<alsa::Error> for DuvetError {
+    fn from(err: alsa::Error) -> Self {
+        Self::AudioDevice(err)
+    }
+}
+
+impl From<hound::Error> for DuvetError {
+    fn from(err: hound::Error) -> Self {
+        match err {
+            hound::Error::IoError(err) => Self::Io(err),
+            err => Self::Encoding(err),
+        }
+    }
+}
@@ -1,8 +1,11 @@
 pub mod audio_out;
+pub mod error;
 pub mod synth;
 pub mod midi_scheduler;
+pub mod midi_input;
 pub mod player;
+pub mod resampler;
+pub mod ring_buffer;
+pub mod engine;
 
-const SAMPLE_RATE: u32 = 48000;
-const BIT_DEPTH: u16 = 8;
-const BUFFER_SIZE: usize = 1024;
\ No newline at end of file
+const DEFAULT_SAMPLE_RATE: u32 = 48000;      // until the output says otherwise
\ No newline at end of file
@@ -0,0 +1,105 @@
+use std::{ffi::CString, io::{self, Read}};
+
+use alsa::{rawmidi::Rawmidi, seq::{Addr, MidiEvent, PortCap, PortType, Seq}, Direction};
+use midly::{live::LiveEvent, stream::MidiStream, MidiMessage};
+
+use crate::error::Result;
+
+const READ_SIZE: usize = 256;
+
+enum MidiSource {
+    // a sequencer port other clients connect to, e.g. `aconnect <keyboard> duvet`
+    Sequencer {
+        seq: Seq,
+        decoder: MidiEvent,
+        port: i32,
+    },
+    // a raw byte stream from a hardware port such as "hw:1,0,0" or a snd-virmidi device
+    Raw(Rawmidi),
+}
+
+// live MIDI coming from an ALSA sequencer port or a rawmidi device
+pub struct MidiInput {
+    source: MidiSource,
+    stream: MidiStream,
+    buffer: [u8; READ_SIZE],
+}
+
+impl MidiInput {
+    pub fn sequencer(client_name: &str, port_name: &str) -> Result<Self> {
+        let seq = Seq::open(None, Some(Direction::Capture), true)?;
+        seq.set_client_name(&cstring(client_name)?)?;
+        let port = seq.create_simple_port(
+            &cstring(port_name)?,
+            PortCap::WRITE | PortCap::SUBS_WRITE,
+            PortType::MIDI_GENERIC | PortType::APPLICATION,
+        )?;
+
+        // every decoded event carries its own status byte
+        let decoder = MidiEvent::new(READ_SIZE as u32)?;
+        decoder.enable_running_status(false);
+
+        Ok(Self::from_source(MidiSource::Sequencer { seq, decoder, port }))
+    }
+
+    pub fn rawmidi(device: &str) -> Result<Self> {
+        let rawmidi = Rawmidi::new(device, Direction::Capture, true)?;
+        Ok(Self::from_source(MidiSource::Raw(rawmidi)))
+    }
+
+    fn from_source(source: MidiSource) -> Self {
+        Self {
+            source,
+            stream: MidiStream::new(),
+            buffer: [0; READ_SIZE],
+        }
+    }
+
+    // sequencer address to connect to, None for rawmidi devices
+    pub fn address(&self) -> Result<Option<Addr>> {
+        match &self.source {
+            MidiSource::Sequencer { seq, port, .. } => {
+                Ok(Some(Addr { client: seq.client_id()?, port: *port }))
+            }
+            MidiSource::Raw(_) => Ok(None),
+        }
+    }
+
+    // reads everything that arrived since the last call without blocking
+    pub fn poll(&mut self, mut handle: impl FnMut(u8, MidiMessage)) -> Result<()> {
+        let mut handle_event = |event: LiveEvent| {
+            if let LiveEvent::Midi { channel, message } = event {
+                handle(channel.as_int(), message);
+            }
+        };
+
+        match &mut self.source {
+            MidiSource::Sequencer { seq, decoder, .. } => {
+                let mut input = seq.input();
+                while input.event_input_pending(true)? > 0 {
+                    let mut event = input.event_input()?;
+                    // non-midi events (port subscriptions, clock...) don't decode
+                    if let Ok(len) = decoder.decode(&mut self.buffer, &mut event) {
+                        self.stream.feed(&self.buffer[..len], &mut handle_event);
+                    }
+                }
+            }
+            MidiSource::Raw(rawmidi) => {
+                // reading an empty non-blocking device fails, so only read what is buffered
+                loop {
+                    let avail = rawmidi.status()?.get_avail().min(READ_SIZE);
+                    if avail == 0 {
+                        break;
+                    }
+                    let len = rawmidi.io().read(&mut self.buffer[..avail])?;
+                    self.stream.feed(&self.buffer[..len], &mut handle_event);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn cstring(name: &str) -> Result<CString> {
+    CString::new(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err).into())
+}
@@ -1,64 +1,60 @@
+pub mod tempo_map;
+
 use std::{fs::File, io::Read, path::Path};
 
 use midly::Smf;
 
+use crate::error::Result;
+
+use tempo_map::TempoMap;
+
 pub struct MidiScheduler {
     events: Vec<(f64, u8, midly::MidiMessage)>, // (timestamp in seconds, channel, MIDI message)
     cursor: usize,
+    tempos: Vec<(f64, f64)>,    // (timestamp in seconds, beats per minute)
+    tempo_cursor: usize,
 }
 
 impl MidiScheduler {
-    pub fn new(file_path: &Path) -> Self {
+    pub fn new(file_path: &Path) -> Result<Self> {
+        let file = File::open(file_path)?;
+        Self::from_reader(file)
+    }
 
-        // opening midi file
-        let mut file = File::open(file_path).unwrap();
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self> {
         let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).unwrap();
+        reader.read_to_end(&mut buffer)?;
+        Self::from_bytes(&buffer)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
 
         // configuring midi reader
-        let smf = Smf::parse(&buffer).unwrap();
-        let mut tempo = 500_000;        // default midi tempo
-        let ticks_per_beat = match smf.header.timing {
-            midly::Timing::Metrical(value) => value.as_int(),
-            _ => 480,
-        };
+        let smf = Smf::parse(bytes)?;
+        let tempo_map = TempoMap::new(&smf)?;
         let mut events = Vec::new();
 
-        // getting tempo
-        for track in &smf.tracks {
-            for event in track {
-                if let midly::TrackEventKind::Meta(message) = &event.kind {
-                    if let midly::MetaMessage::Tempo(t) = message {
-                        tempo = t.as_int();
-                    }
-                }
-            }
-        }
-        
         // reading midi file
         for track in &smf.tracks {
-            let mut time = 0;
+            let mut tick = 0;
             for event in track {
-                time += event.delta.as_int();
+                tick += event.delta.as_int() as u64;
                 if let midly::TrackEventKind::Midi { message, channel } = &event.kind {
-                    let timestamp = time as f64 * (tempo as f64 / 1_000_000.0) / ticks_per_beat as f64;
-                    events.push((timestamp, channel.as_int(), message.clone()));
+                    let timestamp = tempo_map.seconds(tick);
+                    events.push((timestamp, channel.as_int(), *message));
                 }
-                // else if let midly::TrackEventKind::Meta(message) = &event.kind {
-                //     if let midly::MetaMessage::Tempo(t) = message {
-                //         tempo = t.as_int();
-                //     }
-                // }
             }
         }
 
         // sorting events by timestamp
-        events.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        events.sort_by(|a, b| a.0.total_cmp(&b.0));
 
-        Self {
+        Ok(Self {
             events,
             cursor: 0,
-        }
+            tempos: tempo_map.tempos(),
+            tempo_cursor: 0,
+        })
     }
 
     pub fn current_event(&mut self) -> Option<(f64, u8, midly::MidiMessage)> {
@@ -73,4 +69,29 @@ impl MidiScheduler {
     pub fn next_event(&mut self) {
         self.cursor += 1;
     }
+
+    // whether the file picks a program on `channel` anywhere
+    pub fn sets_program(&self, channel: u8) -> bool {
+        self.events.iter().any(|&(_, event_channel, message)| {
+            event_channel == channel && matches!(message, midly::MidiMessage::ProgramChange { .. })
+        })
+    }
+
+    // seconds up to the last event, not counting how long its notes ring
+    pub fn duration(&self) -> f64 {
+        self.events.last().map_or(0., |&(timestamp, _, _)| timestamp)
+    }
+
+    // latest tempo reached by `time` that hasn't been returned yet
+    pub fn tempo_change(&mut self, time: f64) -> Option<f64> {
+        let mut tempo = None;
+        while let Some(&(timestamp, bpm)) = self.tempos.get(self.tempo_cursor) {
+            if timestamp > time {
+                break;
+            }
+            tempo = Some(bpm);
+            self.tempo_cursor += 1;
+        }
+        tempo
+    }
 }
\ No newline at end of file
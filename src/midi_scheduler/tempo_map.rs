@@ -0,0 +1,190 @@
+use std::io;
+
+use midly::{MetaMessage, Smf, Timing, TrackEventKind};
+
+use crate::error::{DuvetError, Result};
+
+const DEFAULT_TEMPO: u32 = 500_000;     // microseconds per beat, 120 bpm
+
+#[derive(Clone, Copy, Debug)]
+struct TempoChange {
+    tick: u64,
+    seconds: f64,       // time at which this tempo starts
+    tempo: u32,         // microseconds per beat
+}
+
+// converts absolute ticks to seconds, honoring every tempo change in the file
+#[derive(Clone, Debug)]
+pub struct TempoMap {
+    ticks_per_beat: u16,
+    ticks_per_second: Option<f64>,      // only set for smpte timecode files
+    changes: Vec<TempoChange>,
+}
+
+impl TempoMap {
+    // fails on a zero timing division, which would put every event at an infinite time
+    pub fn new(smf: &Smf) -> Result<Self> {
+        let ticks_per_beat = match smf.header.timing {
+            Timing::Metrical(value) => value.as_int(),
+            Timing::Timecode(fps, subframes) => {
+                if subframes == 0 {
+                    return Err(invalid_timing("smpte timing with 0 subframes per frame"));
+                }
+                // smpte timing has a fixed tick length, tempo events don't affect it
+                return Ok(Self {
+                    ticks_per_beat: 0,
+                    ticks_per_second: Some(fps.as_f32() as f64 * subframes as f64),
+                    changes: vec![],
+                });
+            }
+        };
+        if ticks_per_beat == 0 {
+            return Err(invalid_timing("metrical timing with 0 ticks per beat"));
+        }
+
+        // merging tempo events from every track
+        let mut tempos = Vec::new();
+        for track in &smf.tracks {
+            let mut tick = 0;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Meta(MetaMessage::Tempo(tempo)) = event.kind {
+                    tempos.push((tick, tempo.as_int()));
+                }
+            }
+        }
+        tempos.sort_by_key(|&(tick, _)| tick);
+
+        let mut changes = vec![TempoChange { tick: 0, seconds: 0., tempo: DEFAULT_TEMPO }];
+        for (tick, tempo) in tempos {
+            let last = changes.last_mut().unwrap();
+            if last.tick == tick {
+                // later events at the same tick win
+                last.tempo = tempo;
+            }
+            else {
+                let seconds = last.seconds + ticks2seconds(tick - last.tick, last.tempo, ticks_per_beat);
+                changes.push(TempoChange { tick, seconds, tempo });
+            }
+        }
+
+        Ok(Self {
+            ticks_per_beat,
+            ticks_per_second: None,
+            changes,
+        })
+    }
+
+    // (seconds, beats per minute) for every tempo in the file, starting with the one at time 0
+    pub fn tempos(&self) -> Vec<(f64, f64)> {
+        self.changes.iter()
+            .map(|change| (change.seconds, 60_000_000. / change.tempo as f64))
+            .collect()
+    }
+
+    pub fn seconds(&self, tick: u64) -> f64 {
+        if let Some(ticks_per_second) = self.ticks_per_second {
+            return tick as f64 / ticks_per_second;
+        }
+
+        // last change at or before the tick
+        let index = self.changes.partition_point(|change| change.tick <= tick) - 1;
+        let change = self.changes[index];
+        change.seconds + ticks2seconds(tick - change.tick, change.tempo, self.ticks_per_beat)
+    }
+}
+
+fn invalid_timing(message: &str) -> DuvetError {
+    io::Error::new(io::ErrorKind::InvalidData, message).into()
+}
+
+fn ticks2seconds(ticks: u64, tempo: u32, ticks_per_beat: u16) -> f64 {
+    ticks as f64 * (tempo as f64 / 1_000_000.0) / ticks_per_beat as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::{num::{u15, u24, u28, u4, u7}, Format, Fps, Header, MidiMessage, TrackEvent};
+
+    use super::*;
+    use crate::midi_scheduler::MidiScheduler;
+
+    const TICKS_PER_BEAT: u16 = 480;
+
+    fn tempo(delta: u32, microseconds: u32) -> TrackEvent<'static> {
+        TrackEvent { delta: u28::new(delta), kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(microseconds))) }
+    }
+
+    fn note(delta: u32, key: u8) -> TrackEvent<'static> {
+        let message = MidiMessage::NoteOn { key: u7::new(key), vel: u7::new(100) };
+        TrackEvent { delta: u28::new(delta), kind: TrackEventKind::Midi { channel: u4::new(0), message } }
+    }
+
+    fn end() -> TrackEvent<'static> {
+        TrackEvent { delta: u28::new(0), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) }
+    }
+
+    // 120 bpm, 240 bpm from beat 2, 60 bpm from beat 4, the last change in the note track
+    fn song() -> Smf<'static> {
+        let conductor = vec![tempo(0, 500_000), tempo(960, 250_000), end()];
+        let notes = vec![note(480, 60), note(480, 62), note(480, 64), note(480, 65), tempo(0, 1_000_000), note(480, 67), end()];
+        Smf {
+            header: Header::new(Format::Parallel, Timing::Metrical(u15::new(TICKS_PER_BEAT))),
+            tracks: vec![conductor, notes],
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} s, expected {expected} s");
+    }
+
+    #[test]
+    fn seconds_across_tempo_changes() {
+        let map = TempoMap::new(&song()).unwrap();
+        let expected = [(0, 0.), (480, 0.5), (960, 1.), (1440, 1.25), (1920, 1.5), (2400, 2.5), (2880, 3.5)];
+        for (tick, seconds) in expected {
+            assert_close(map.seconds(tick), seconds);
+        }
+
+        let tempos = map.tempos();
+        assert_eq!(tempos.len(), 3);
+        for (&(seconds, bpm), (expected_seconds, expected_bpm)) in tempos.iter().zip([(0., 120.), (1., 240.), (1.5, 60.)]) {
+            assert_close(seconds, expected_seconds);
+            assert_close(bpm, expected_bpm);
+        }
+    }
+
+    #[test]
+    fn scheduled_events_across_tempo_changes() {
+        let mut bytes = vec![];
+        song().write_std(&mut bytes).unwrap();
+        let mut scheduler = MidiScheduler::from_bytes(&bytes).unwrap();
+
+        for expected in [0.5, 1., 1.25, 1.5, 2.5] {
+            let (seconds, _, _) = scheduler.current_event().unwrap();
+            assert_close(seconds, expected);
+            scheduler.next_event();
+        }
+        assert!(scheduler.current_event().is_none());
+        assert_close(scheduler.duration(), 2.5);
+    }
+
+    #[test]
+    fn timecode_ignores_tempo() {
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Timecode(Fps::Fps25, 40)),
+            tracks: vec![vec![tempo(0, 250_000), note(500, 60), end()]],
+        };
+        let map = TempoMap::new(&smf).unwrap();
+        assert_close(map.seconds(500), 0.5);
+        assert_close(map.seconds(2500), 2.5);
+    }
+
+    #[test]
+    fn zero_timing_division_is_an_error() {
+        let metrical = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(u15::new(0))));
+        assert!(TempoMap::new(&metrical).is_err());
+        let timecode = Smf::new(Header::new(Format::SingleTrack, Timing::Timecode(Fps::Fps30, 0)));
+        assert!(TempoMap::new(&timecode).is_err());
+    }
+}
@@ -1,128 +1,319 @@
-use std::{collections::HashSet, io::Read, path::Path};
+use std::{collections::HashMap, io::{stdout, Read, Stdout, Write}, path::Path};
 
-use midly::MidiMessage;
-use termion::{async_stdin, event::Key, input::TermRead};
+use termion::{async_stdin, clear, event::Key, input::TermRead, raw::{IntoRawMode, RawTerminal}};
 
-use crate::{audio_out::{AudioMode, AudioOut}, synth::instrument::Instrument, midi_scheduler::MidiScheduler, synth::Synth, SAMPLE_RATE};
+use crate::{audio_out::{AudioMode, AudioOut, Channels, OutputConfig}, error::Result, midi_input::MidiInput, midi_scheduler::MidiScheduler, synth::{instrument::Instrument, Synth}};
 
 pub struct MidiPlayer {
     scheduler: MidiScheduler,
 }
 
 impl MidiPlayer {
-    pub fn new(file_path: &Path) -> Self {
-        let scheduler = MidiScheduler::new(file_path);
+    pub fn new(file_path: &Path) -> Result<Self> {
+        let scheduler = MidiScheduler::new(file_path)?;
+        Ok(Self::from_scheduler(scheduler))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let scheduler = MidiScheduler::from_bytes(bytes)?;
+        Ok(Self::from_scheduler(scheduler))
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let scheduler = MidiScheduler::from_reader(reader)?;
+        Ok(Self::from_scheduler(scheduler))
+    }
+
+    fn from_scheduler(scheduler: MidiScheduler) -> Self {
         Self {
             scheduler,
         }
     }
 
-    fn update(&mut self, synth: &mut Synth, time: f64) -> bool {
-        if let Some((timestamp, channel, message)) = self.scheduler.current_event() {
-            if timestamp <= time {
-                match message {
-                    MidiMessage::NoteOn { key, vel } => {
-                        if vel == 0 {
-                            synth.note_off(channel, key.as_int());
-                        }
-                        else {
-                            synth.note_on(channel, key.as_int());
-                        }
-                    }
-                    MidiMessage::NoteOff { key, .. } => {
-                        synth.note_off(channel, key.as_int());
-                    }
-                    _ => ()
-                }
+    // seconds up to the last event
+    pub fn duration(&self) -> f64 {
+        self.scheduler.duration()
+    }
+
+    // the sample the last event plays on
+    pub fn end(&self, sample_rate: u32) -> u64 {
+        (self.duration() * sample_rate as f64).ceil() as u64
+    }
+
+    pub fn sets_program(&self, channel: u8) -> bool {
+        self.scheduler.sets_program(channel)
+    }
 
-                self.scheduler.next_event();
+    // dispatches every event due by sample `clock`, returns when the next one is due or None at the end
+    fn update(&mut self, synth: &mut Synth, clock: u64, sample_rate: u32) -> Option<u64> {
+        if let Some(bpm) = self.scheduler.tempo_change(clock as f64 / sample_rate as f64) {
+            synth.set_tempo(bpm as f32);
+        }
+        while let Some((timestamp, channel, message)) = self.scheduler.current_event() {
+            // the first sample at or after the event
+            let due = (timestamp * sample_rate as f64).ceil() as u64;
+            if due > clock {
+                return Some(due);
             }
-            true
+            synth.dispatch(channel, message);
+            self.scheduler.next_event();
         }
-        else {
-            false
+        None
+    }
+
+    // renders `buffer` from sample `clock` on, split so every event due inside it lands on its exact sample
+    // returns when the next event is due, or None once the last one has played
+    pub fn render_block(&mut self, synth: &mut Synth, clock: u64, sample_rate: u32, buffer: &mut [f32], channels: Channels) -> Option<u64> {
+        let samples_per_frame = channels.count() as usize;
+        let frames = buffer.len() / samples_per_frame;
+
+        let mut rendered = 0;
+        loop {
+            let next = self.update(synth, clock + rendered as u64, sample_rate);
+            let end = next.map_or(frames, |due| frames.min((due - clock) as usize));
+
+            synth.render_channels(&mut buffer[rendered * samples_per_frame..end * samples_per_frame], channels);
+            rendered = end;
+            if rendered == frames {
+                return next;
+            }
+        }
+    }
+}
+
+const SONG_VOLUME: f32 = 0.1;
+
+// the instruments duvet.mid was arranged for, it doesn't pick any programs itself
+// only for that song, other files keep the factory defaults, and channels a file does pick a program on are left alone
+pub fn song_presets(synth: &mut Synth, midi_player: &MidiPlayer) {
+    for channel in 0..16 {
+        if let Some(preset) = song_preset(channel) {
+            if !midi_player.sets_program(channel) {
+                synth.add_instrument(channel, preset(SONG_VOLUME));
+            }
         }
     }
 }
 
+fn song_preset(channel: u8) -> Option<fn(f32) -> Instrument> {
+    let preset = match channel {
+        0 => Instrument::lead_square,       // voice
+        1 => Instrument::lead_square,       // bass
+        2 => Instrument::lead_sawtooth,     // guitar
+        3 => Instrument::lead_square,       // bell
+        4 => Instrument::lead_sine,         // bass 2
+        5 => Instrument::lead_triangle,     // violin
+        6 => Instrument::lead_sawtooth,     // guitar 2
+        7 => Instrument::lead_sine,         // sine
+        11 => Instrument::lead_triangle,    // voice 2
+        14 => Instrument::lead_square,      // guitar 3
+        _ => return None,
+    };
+    Some(preset)
+}
+
+const KEYBOARD_POLL_INTERVAL: f64 = 0.002;     // seconds between stdin polls
+const DEFAULT_GATE_TIME: f64 = 0.6;           // longer than the usual key repeat delay
+const KEYBOARD_VELOCITY: u8 = 100;            // terminals don't report how hard keys are hit
+
+// terminals only report key presses, so held keys are detected through key repeat
+// and notes are released once no repeat arrives for `gate_time` seconds
 pub struct KeyboardPlayer {
-    keys_pressed: HashSet<Key>,
     stdin: termion::AsyncReader,
+    stdout: RawTerminal<Stdout>,
+    keys_pressed: HashMap<u8, f64>,     // MIDI note -> time of last press or repeat
     current_channel: u8,
+    octave: u8,
+    sustain: bool,
+    gate_time: f64,
+    next_poll: f64,
 }
 
 impl KeyboardPlayer {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self> {
         let stdin = async_stdin();
-        let keys_pressed = HashSet::new();
+        let stdout = stdout().into_raw_mode()?;
 
-        Self {
+        let mut player = Self {
             stdin,
-            keys_pressed,
+            stdout,
+            keys_pressed: HashMap::new(),
             current_channel: 0,
-        }
+            octave: 4,
+            sustain: false,
+            gate_time: DEFAULT_GATE_TIME,
+            next_poll: 0.,
+        };
+        player.print_status()?;
+        Ok(player)
+    }
+
+    pub fn set_gate_time(&mut self, gate_time: f64) {
+        self.gate_time = gate_time;
+    }
+
+    pub fn gate_time(&self) -> f64 {
+        self.gate_time
     }
 
-    pub fn update(&mut self, synth: &mut Synth, time: f64) -> bool {
-        let keys: Vec<_> = self.stdin.by_ref().events().collect();
+    pub fn update(&mut self, synth: &mut Synth, time: f64) -> Result<bool> {
+        if time < self.next_poll {
+            return Ok(true);
+        }
+        self.next_poll = time + KEYBOARD_POLL_INTERVAL;
+
+        let keys: Vec<_> = self.stdin.by_ref().keys().collect();
         for key in keys {
-            println!("{:?}", key);
-            // if let Ok(key) = key {
-            //     match key {
-            //         Key::Char('a') => self.handle_key_event(Key::Char('a'), 60, synth), // C4
-            //         Key::Char('s') => self.handle_key_event(Key::Char('s'), 62, synth), // D4
-            //         Key::Char('d') => self.handle_key_event(Key::Char('d'), 64, synth), // E4
-            //         Key::Char('f') => self.handle_key_event(Key::Char('f'), 65, synth), // F4
-            //         Key::Char('g') => self.handle_key_event(Key::Char('g'), 67, synth), // G4
-            //         _ => (),
-            //     }
-            // }
-        }
-
-        // Handle key releases
-        let mut keys_pressed = self.keys_pressed.clone();
-        keys_pressed.retain(|&key| {
-            match key {
-                Key::Char('a') => self.handle_key_release(Key::Char('a'), 60, synth),
-                Key::Char('s') => self.handle_key_release(Key::Char('s'), 62, synth),
-                Key::Char('d') => self.handle_key_release(Key::Char('d'), 64, synth),
-                Key::Char('f') => self.handle_key_release(Key::Char('f'), 65, synth),
-                Key::Char('g') => self.handle_key_release(Key::Char('g'), 67, synth),
-                Key::Char('q') => return false,
-                _ => true,
+            match key? {
+                Key::Esc | Key::Ctrl('c') => {
+                    self.release_all(synth);
+                    synth.control_change(self.current_channel, 64, 0);
+                    write!(self.stdout, "\r\n")?;
+                    return Ok(false);
+                }
+                Key::Up => self.octave = (self.octave + 1).min(8),
+                Key::Down => self.octave = self.octave.saturating_sub(1),
+                Key::Right => self.set_channel((self.current_channel + 1) % 16, synth),
+                Key::Left => self.set_channel((self.current_channel + 15) % 16, synth),
+                Key::Char(' ') => {
+                    self.sustain = !self.sustain;
+                    synth.control_change(self.current_channel, 64, if self.sustain { 127 } else { 0 });
+                }
+                Key::Char(c) => {
+                    if let Some(offset) = key_offset(c) {
+                        let note = 12 * (self.octave as i32 + 1) + offset;
+                        if (0..128).contains(&note) {
+                            self.handle_key_event(note as u8, time, synth);
+                        }
+                    }
+                    continue;
+                }
+                _ => continue,
             }
+            self.print_status()?;
+        }
+
+        // handle key releases
+        let (channel, gate_time) = (self.current_channel, self.gate_time);
+        self.keys_pressed.retain(|&note, &mut last| {
+            if time - last < gate_time {
+                return true;
+            }
+            synth.note_off(channel, note);
+            false
         });
-        self.keys_pressed = keys_pressed;
-        true
+
+        Ok(true)
     }
-    
-    fn handle_key_event(&mut self, key: Key, note: u8, synth: &mut Synth) {
-        println!("aqui!");
-        if !self.keys_pressed.contains(&key) {
-            synth.note_on(self.current_channel, note);
-            self.keys_pressed.insert(key);
+
+    fn handle_key_event(&mut self, note: u8, time: f64, synth: &mut Synth) {
+        // repeats of a held key only extend the gate
+        if self.keys_pressed.insert(note, time).is_none() {
+            synth.note_on(self.current_channel, note, KEYBOARD_VELOCITY);
+        }
+    }
+
+    // the sustain pedal follows the player to the new channel
+    fn set_channel(&mut self, channel: u8, synth: &mut Synth) {
+        self.release_all(synth);
+        if self.sustain {
+            synth.control_change(self.current_channel, 64, 0);
+            synth.control_change(channel, 64, 127);
         }
+        self.current_channel = channel;
     }
 
-    fn handle_key_release(&mut self, key: Key, note: u8, synth: &mut Synth) -> bool {
-        if !self.stdin.by_ref().keys().any(|k| matches!(k, Ok(key))) {
+    fn release_all(&mut self, synth: &mut Synth) {
+        for (note, _) in self.keys_pressed.drain() {
             synth.note_off(self.current_channel, note);
-            return false;
         }
-        true
+    }
+
+    fn print_status(&mut self) -> Result<()> {
+        write!(
+            self.stdout,
+            "\r{}channel {:>2} | octave {} | sustain {} | arrows: octave/channel, space: sustain, esc: quit",
+            clear::CurrentLine,
+            self.current_channel + 1,
+            self.octave,
+            if self.sustain { "on " } else { "off" },
+        )?;
+        self.stdout.flush()?;
+        Ok(())
+    }
+}
+
+// two row tracker layout: the bottom row starts at the current octave, the top row one octave up
+fn key_offset(key: char) -> Option<i32> {
+    let offset = match key {
+        'z' => 0, 's' => 1, 'x' => 2, 'd' => 3, 'c' => 4, 'v' => 5, 'g' => 6,
+        'b' => 7, 'h' => 8, 'n' => 9, 'j' => 10, 'm' => 11,
+        ',' => 12, 'l' => 13, '.' => 14, ';' => 15, '/' => 16,
+
+        'q' => 12, '2' => 13, 'w' => 14, '3' => 15, 'e' => 16, 'r' => 17, '5' => 18,
+        't' => 19, '6' => 20, 'y' => 21, '7' => 22, 'u' => 23,
+        'i' => 24, '9' => 25, 'o' => 26, '0' => 27, 'p' => 28,
+        _ => return None,
+    };
+    Some(offset)
+}
+
+const INPUT_POLL_INTERVAL: f64 = 0.001;
+
+// plays MIDI arriving live from other programs or hardware, see `MidiInput`
+pub struct InputPlayer {
+    input: MidiInput,
+    next_poll: f64,
+}
+
+impl InputPlayer {
+    pub fn sequencer(port_name: &str) -> Result<Self> {
+        let input = MidiInput::sequencer("duvet", port_name)?;
+        Ok(Self::from_input(input))
+    }
+
+    pub fn rawmidi(device: &str) -> Result<Self> {
+        let input = MidiInput::rawmidi(device)?;
+        Ok(Self::from_input(input))
+    }
+
+    pub fn from_input(input: MidiInput) -> Self {
+        Self {
+            input,
+            next_poll: 0.,
+        }
+    }
+
+    pub fn input(&self) -> &MidiInput {
+        &self.input
+    }
+
+    fn update(&mut self, synth: &mut Synth, time: f64) -> Result<bool> {
+        if time >= self.next_poll {
+            self.next_poll = time + INPUT_POLL_INTERVAL;
+            self.input.poll(|channel, message| synth.dispatch(channel, message))?;
+        }
+        Ok(true)
     }
 }
 
 pub enum PlayerKind {
     Keyboard(KeyboardPlayer),
     Midi(MidiPlayer),
+    Input(InputPlayer),
     Both(KeyboardPlayer, MidiPlayer),
 }
 
+const DEFAULT_BLOCK_SIZE: usize = 128;      // frames rendered per update
+
 pub struct Player {
     synth: Synth,
     out: AudioOut,
-    time: f64,
+    clock: u64,             // samples rendered so far
+    block: Vec<f32>,
+    block_size: usize,
+    max_tail: f64,
+    tail: Option<Tail>,     // once a file's last event has played
     
     kind: PlayerKind,
     // better way to implement this maybe??         <--- come back to this
@@ -131,80 +322,417 @@ pub struct Player {
 }
 
 impl Player {
-    pub fn new(kind: PlayerKind, audio_mode: AudioMode) -> Self {
-        let mut synth = Synth::new();
+    pub fn new(kind: PlayerKind, audio_mode: AudioMode) -> Result<Self> {
+        Self::with_channels(kind, audio_mode, Channels::Mono)
+    }
 
-        let voice = Instrument::lead_square(0.1);
-        let bass = Instrument::lead_square(0.1);
-        let bass2 = Instrument::lead_sine(0.1);
-        let guitar = Instrument::lead_sawtooth(0.1);
-        let guitar2 = Instrument::lead_sawtooth(0.1);
-        let violin = Instrument::lead_triangle(0.1);
-        let bell = Instrument::lead_square(0.1);
-        let sine = Instrument::lead_sine(0.1);
-        let drums = Instrument::drum_kit(0.25);
-        let voice2 = Instrument::lead_triangle(0.1);
-        let guitar3 = Instrument::lead_square(0.1);
-
-        synth.add_instrument(0, voice);
-        synth.add_instrument(1, bass);
-        synth.add_instrument(4, bass2);
-        synth.add_instrument(2, guitar);
-        synth.add_instrument(6, guitar2);
-        synth.add_instrument(5, violin);
-        synth.add_instrument(3, bell);
-        synth.add_instrument(7, sine);
-        synth.add_instrument(9, drums);
-        synth.add_instrument(11, voice2);
-        synth.add_instrument(14, guitar3);
-
-        let out = AudioOut::new(audio_mode);
+    // stereo renders panned channels and stereo effects, telephone modes stay mono
+    pub fn with_channels(kind: PlayerKind, audio_mode: AudioMode, channels: Channels) -> Result<Self> {
+        let out = AudioOut::with_channels(audio_mode, channels)?;
+        Ok(Self::with_output(kind, out))
+    }
 
+    // for outputs set up by hand, e.g. with a different sample format or rate
+    pub fn with_output(kind: PlayerKind, out: AudioOut) -> Self {
+        let mut synth = Synth::new();
+        synth.set_sample_rate(out.sample_rate());
         Self {
             synth,
             kind,
             out,
-            time: 0.,
+            clock: 0,
+            block: vec![],
+            block_size: DEFAULT_BLOCK_SIZE,
+            max_tail: DEFAULT_MAX_TAIL,
+            tail: None,
         }
     }
 
-    pub fn new_midi(file_path: &Path, audio_mode: AudioMode) -> Self {
-        let midi_player = MidiPlayer::new(file_path);
+    // plays the file with duvet.mid's instruments, see `song_presets`
+    pub fn add_song_presets(&mut self) {
+        if let PlayerKind::Midi(midi_player) | PlayerKind::Both(_, midi_player) = &self.kind {
+            song_presets(&mut self.synth, midi_player);
+        }
+    }
+
+    // smaller blocks poll live input more often, file events land on their exact sample either way
+    pub fn set_block_size(&mut self, block_size: usize) {
+        self.block_size = block_size.max(1);
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    // caps how long a file keeps playing past its last event while the notes and effects ring out
+    pub fn set_max_tail(&mut self, seconds: f64) {
+        self.max_tail = seconds.max(0.);
+    }
+
+    pub fn max_tail(&self) -> f64 {
+        self.max_tail
+    }
+
+    // seconds played so far
+    pub fn time(&self) -> f64 {
+        self.clock as f64 / self.out.sample_rate() as f64
+    }
+
+    pub fn synth(&self) -> &Synth {
+        &self.synth
+    }
+
+    pub fn synth_mut(&mut self) -> &mut Synth {
+        &mut self.synth
+    }
+
+    pub fn new_midi(file_path: &Path, audio_mode: AudioMode) -> Result<Self> {
+        let midi_player = MidiPlayer::new(file_path)?;
+        Self::new(PlayerKind::Midi(midi_player), audio_mode)
+    }
+
+    pub fn new_midi_bytes(bytes: &[u8], audio_mode: AudioMode) -> Result<Self> {
+        let midi_player = MidiPlayer::from_bytes(bytes)?;
         Self::new(PlayerKind::Midi(midi_player), audio_mode)
     }
 
-    pub fn new_keyboard(audio_mode: AudioMode) -> Self {
-        let keyboard_player = KeyboardPlayer::new();
+    pub fn new_midi_reader<R: Read>(reader: R, audio_mode: AudioMode) -> Result<Self> {
+        let midi_player = MidiPlayer::from_reader(reader)?;
+        Self::new(PlayerKind::Midi(midi_player), audio_mode)
+    }
+
+    pub fn new_keyboard(audio_mode: AudioMode) -> Result<Self> {
+        let keyboard_player = KeyboardPlayer::new()?;
         Self::new(PlayerKind::Keyboard(keyboard_player), audio_mode)
     }
 
-    pub fn update(&mut self) -> bool {
-        let condition;
-        match &mut self.kind {
-            PlayerKind::Midi(midi_player) => {
-                condition = midi_player.update(&mut self.synth, self.time);
+    pub fn new_input(input_player: InputPlayer, audio_mode: AudioMode) -> Result<Self> {
+        Self::new(PlayerKind::Input(input_player), audio_mode)
+    }
+
+    // renders one block, split wherever a file event falls inside it
+    pub fn update(&mut self) -> Result<bool> {
+        let sample_rate = self.out.sample_rate();
+        let time = self.time();
+
+        // live input is polled once per block
+        let mut condition = match &mut self.kind {
+            PlayerKind::Keyboard(keyboard_player) | PlayerKind::Both(keyboard_player, _) => {
+                keyboard_player.update(&mut self.synth, time)?
             }
-            PlayerKind::Keyboard(keyboard_player) => {
-                condition = keyboard_player.update(&mut self.synth, self.time);
+            PlayerKind::Input(input_player) => {
+                input_player.update(&mut self.synth, time)?
             }
-            PlayerKind::Both(keyboard_player, midi_player) => {
-                midi_player.update(&mut self.synth, self.time);
-                condition = keyboard_player.update(&mut self.synth, self.time);
+            PlayerKind::Midi(_) => true,
+        };
+
+        let channels = self.out.channels();
+        self.block.resize(self.block_size * channels.count() as usize, 0.);
+
+        let mut tail_frames = self.block_size;
+        match &mut self.kind {
+            PlayerKind::Midi(midi_player) if self.tail.is_none() => {
+                if midi_player.render_block(&mut self.synth, self.clock, sample_rate, &mut self.block, channels).is_none() {
+                    self.tail = Some(Tail::new(sample_rate, self.max_tail));
+                    // only the part after the last event counts toward the tail
+                    let song_end = midi_player.end(sample_rate).max(self.clock);
+                    tail_frames = (self.clock + self.block_size as u64).saturating_sub(song_end) as usize;
+                }
+            }
+            PlayerKind::Both(_, midi_player) => {
+                midi_player.render_block(&mut self.synth, self.clock, sample_rate, &mut self.block, channels);
             }
+            PlayerKind::Midi(_) | PlayerKind::Keyboard(_) | PlayerKind::Input(_) => self.synth.render_channels(&mut self.block, channels),
+        }
+
+        // the song is over once its last notes and effects have rung out
+        if let Some(tail) = &mut self.tail {
+            condition = tail.update(&self.synth, &self.block, tail_frames);
         }
 
-        let sample = self.synth.next_sample();
-        self.out.send(bipolar2u8(sample));
-        self.time += 1. / SAMPLE_RATE as f64;
-        condition
+        self.out.write(&self.block)?;
+        self.clock += self.block_size as u64;
+        Ok(condition)
     }
 
-    pub fn drain(&mut self) {
+    pub fn drain(&mut self) -> Result<()> {
         self.out.drain()
     }
 }
 
-pub fn bipolar2u8(sample: f32) -> u8 {
-    let sample = ((sample + 1.) / 2.) * 255.;
-    sample.round() as u8
-}
\ No newline at end of file
+const RENDER_BLOCK_SIZE: usize = 4096;      // frames, nothing live to react to so bigger is faster
+pub const DEFAULT_MAX_TAIL: f64 = 10.;      // seconds rendered past the last event at most
+const SILENCE_THRESHOLD: f32 = 1e-4;        // -80 dBFS
+const SILENCE_HOLD: f64 = 1.;               // seconds without sound after the last voice before the effects count as done
+
+// follows the sound after a song's last event, so whoever renders it knows when the release tails and effects have died away
+pub struct Tail {
+    length: u64,        // frames since the last event
+    silence: u64,       // frames since anything was heard
+    max_tail: u64,
+    hold: u64,
+}
+
+impl Tail {
+    pub fn new(sample_rate: u32, max_tail: f64) -> Self {
+        Self {
+            length: 0,
+            silence: 0,
+            max_tail: (max_tail.max(0.) * sample_rate as f64) as u64,
+            hold: (SILENCE_HOLD * sample_rate as f64) as u64,
+        }
+    }
+
+    // takes the next `frames` frames rendered after the last event, returns false once the tail is over
+    pub fn update(&mut self, synth: &Synth, block: &[f32], frames: usize) -> bool {
+        self.length += frames as u64;
+        if !synth.is_idle() || block.iter().any(|sample| sample.abs() > SILENCE_THRESHOLD) {
+            self.silence = 0;
+        }
+        else {
+            self.silence += frames as u64;
+        }
+        self.silence < self.hold && self.length < self.max_tail
+    }
+
+    // frames of silence at the end so far, none if the last block could be heard
+    pub fn silence(&self) -> u64 {
+        self.silence
+    }
+
+    // frames left before the tail is cut off
+    pub fn remaining(&self) -> u64 {
+        self.max_tail.saturating_sub(self.length)
+    }
+}
+
+// renders a whole file as fast as the synth goes, letting release tails and effects ring out past the last event
+pub struct OfflineRender {
+    midi_player: MidiPlayer,
+    synth: Synth,
+    max_tail: f64,
+}
+
+impl OfflineRender {
+    pub fn new(midi_player: MidiPlayer) -> Self {
+        Self {
+            midi_player,
+            synth: Synth::new(),
+            max_tail: DEFAULT_MAX_TAIL,
+        }
+    }
+
+    // renders the file with duvet.mid's instruments, see `song_presets`
+    pub fn add_song_presets(&mut self) {
+        song_presets(&mut self.synth, &self.midi_player);
+    }
+
+    pub fn synth(&self) -> &Synth {
+        &self.synth
+    }
+
+    pub fn synth_mut(&mut self) -> &mut Synth {
+        &mut self.synth
+    }
+
+    // caps the tail for notes that never end, like a held pedal or a drone
+    pub fn set_max_tail(&mut self, seconds: f64) {
+        self.max_tail = seconds.max(0.);
+    }
+
+    pub fn max_tail(&self) -> f64 {
+        self.max_tail
+    }
+
+    // writes a wav file at `path` and returns how many seconds long it is
+    // `progress` gets the fraction of the song rendered after every block, staying at 1 through the tail
+    pub fn render(mut self, path: &Path, config: OutputConfig, mut progress: impl FnMut(f64)) -> Result<f64> {
+        let mut out = AudioOut::with_config(AudioMode::File(path.to_path_buf()), config)?;
+        let sample_rate = out.sample_rate();
+        let channels = out.channels();
+        let samples_per_frame = channels.count() as usize;
+        self.synth.set_sample_rate(sample_rate);
+
+        let duration = self.midi_player.duration();
+        let song_end = self.midi_player.end(sample_rate);
+        let mut tail = Tail::new(sample_rate, self.max_tail);
+        let limit = song_end + tail.remaining();
+
+        let mut block = vec![0.; RENDER_BLOCK_SIZE * samples_per_frame];
+        let mut silence = vec![];       // rendered since the last sound, only written if more follows
+        let mut clock: u64 = 0;
+        let mut written: u64 = 0;
+        let mut song_over = false;
+
+        loop {
+            // no block runs past the tail cap, the one the song ends in included
+            let frames = RENDER_BLOCK_SIZE.min((limit - clock) as usize);
+            let block = &mut block[..frames * samples_per_frame];
+            if song_over {
+                self.synth.render_channels(block, channels);
+            }
+            else if self.midi_player.render_block(&mut self.synth, clock, sample_rate, block, channels).is_none() {
+                song_over = true;
+            }
+            let start = clock;
+            clock += frames as u64;
+
+            if !song_over {
+                out.write(block)?;
+                written = clock;
+                progress(if duration > 0. { (clock as f64 / sample_rate as f64 / duration).min(1.) } else { 1. });
+                continue;
+            };
+            progress(1.);
+
+            // only the part after the last event counts toward the tail
+            let ringing = tail.update(&self.synth, block, clock.saturating_sub(start.max(song_end)) as usize);
+            if tail.silence() == 0 {
+                out.write(&silence)?;
+                out.write(block)?;
+                silence.clear();
+                written = clock;
+            }
+            else {
+                silence.extend_from_slice(block);
+            }
+            if !ringing {
+                break;
+            }
+        }
+
+        out.drain()?;
+        Ok(written as f64 / sample_rate as f64)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use midly::{num::{u15, u28, u4, u7}, Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+    const BLOCK_SIZE: usize = 128;
+    const TICKS_PER_SECOND: f64 = 960.;     // 480 ticks per beat at the default 120 bpm
+
+    fn event(delta: u32, message: MidiMessage) -> TrackEvent<'static> {
+        TrackEvent { delta: u28::new(delta), kind: TrackEventKind::Midi { channel: u4::new(0), message } }
+    }
+
+    fn note_on(delta: u32) -> TrackEvent<'static> {
+        event(delta, MidiMessage::NoteOn { key: u7::new(69), vel: u7::new(100) })
+    }
+
+    fn file(mut track: Vec<TrackEvent<'static>>) -> Vec<u8> {
+        track.push(TrackEvent { delta: u28::new(0), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) });
+        let smf = Smf { header: Header::new(Format::SingleTrack, Timing::Metrical(u15::new(480))), tracks: vec![track] };
+        let mut bytes = vec![];
+        smf.write_std(&mut bytes).unwrap();
+        bytes
+    }
+
+    // a single half second note starting `tick` ticks in
+    pub(crate) fn song(tick: u32) -> Vec<u8> {
+        file(vec![note_on(tick), event(480, MidiMessage::NoteOff { key: u7::new(69), vel: u7::new(0) })])
+    }
+
+    // a note that never ends, starting `tick` ticks in
+    fn drone(tick: u32) -> Vec<u8> {
+        file(vec![note_on(tick)])
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("duvet-{name}-{}.wav", process::id()))
+    }
+
+    // frames in the wav at `path`, which is removed
+    fn take_frames(path: &Path) -> u64 {
+        let frames = hound::WavReader::open(path).unwrap().duration();
+        fs::remove_file(path).unwrap();
+        frames as u64
+    }
+
+    fn config() -> OutputConfig {
+        OutputConfig { sample_rate: SAMPLE_RATE, ..Default::default() }
+    }
+
+    #[test]
+    fn events_land_on_their_exact_sample() {
+        let tick = 7;
+        let due = (tick as f64 / TICKS_PER_SECOND * SAMPLE_RATE as f64).ceil() as usize;
+        assert_ne!(due % BLOCK_SIZE, 0);
+
+        let mut midi_player = MidiPlayer::from_bytes(&song(tick)).unwrap();
+        let mut synth = Synth::new();
+        synth.set_sample_rate(SAMPLE_RATE);
+        synth.add_instrument(0, Instrument::lead_triangle(1.));
+
+        let mut output = vec![0.; 4 * BLOCK_SIZE];
+        for (index, block) in output.chunks_mut(BLOCK_SIZE).enumerate() {
+            midi_player.render_block(&mut synth, (index * BLOCK_SIZE) as u64, SAMPLE_RATE, block, Channels::Mono);
+        }
+        assert_eq!(output.iter().position(|&sample| sample != 0.), Some(due));
+    }
+
+    // renders a drone starting 7 ticks in with the tail capped at `max_tail` seconds, which should be exactly where it stops
+    fn assert_offline_tail_capped(max_tail: f64) {
+        let tick = 7;
+        let path = temp_path("offline-tail");
+        let mut render = OfflineRender::new(MidiPlayer::from_bytes(&drone(tick)).unwrap());
+        render.set_max_tail(max_tail);
+        let seconds = render.render(&path, config(), |_| ()).unwrap();
+
+        let song_end = (tick as f64 / TICKS_PER_SECOND * SAMPLE_RATE as f64).ceil() as u64;
+        let expected = song_end + (max_tail * SAMPLE_RATE as f64) as u64;
+        assert_eq!(take_frames(&path), expected);
+        assert_eq!(seconds, expected as f64 / SAMPLE_RATE as f64);
+    }
+
+    #[test]
+    fn offline_tail_stops_at_the_cap() {
+        assert_offline_tail_capped(0.25);
+    }
+
+    // the cap falls inside the block the song ends in
+    #[test]
+    fn offline_tail_shorter_than_a_block() {
+        assert!(0.01 * (SAMPLE_RATE as f64) < RENDER_BLOCK_SIZE as f64);
+        assert_offline_tail_capped(0.01);
+    }
+
+    #[test]
+    fn player_rings_out_the_last_notes() {
+        let path = temp_path("player-tail");
+        let out = AudioOut::with_config(AudioMode::File(path.clone()), config()).unwrap();
+        let mut player = Player::with_output(PlayerKind::Midi(MidiPlayer::from_bytes(&song(0)).unwrap()), out);
+        while player.update().unwrap() {}
+        player.drain().unwrap();
+
+        assert!(player.synth().is_idle());
+        assert!(player.time() > 0.5 && player.time() < 0.5 + DEFAULT_MAX_TAIL, "{} s", player.time());
+        assert_eq!(take_frames(&path), (player.time() * SAMPLE_RATE as f64).round() as u64);
+    }
+
+    #[test]
+    fn player_tail_starts_at_the_last_event() {
+        let tick = 7;
+        let max_tail = 0.011;
+        let path = temp_path("player-cap");
+        let out = AudioOut::with_config(AudioMode::File(path.clone()), config()).unwrap();
+        let mut player = Player::with_output(PlayerKind::Midi(MidiPlayer::from_bytes(&drone(tick)).unwrap()), out);
+        player.set_block_size(BLOCK_SIZE);
+        player.set_max_tail(max_tail);
+        while player.update().unwrap() {}
+        player.drain().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // real time playback stops at the end of the block the cap falls in
+        let song_end = (tick as f64 / TICKS_PER_SECOND * SAMPLE_RATE as f64).ceil() as u64;
+        let cap = song_end + (max_tail * SAMPLE_RATE as f64) as u64;
+        let played = (player.time() * SAMPLE_RATE as f64).round() as u64;
+        assert!(played >= cap && played < cap + BLOCK_SIZE as u64, "stopped at {played}, capped at {cap}");
+    }
+}
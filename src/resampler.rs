@@ -0,0 +1,269 @@
+use std::{f64::consts::PI, io};
+
+use crate::error::Result;
+
+const PHASES: usize = 512;          // kernel tables between two input samples, interpolated in between
+const PASSBAND: f64 = 0.9;          // flat up to this fraction of the lower nyquist, rolled off by the nyquist itself
+
+// trades filter length for steepness and stopband rejection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Quality {
+    Fast,           // about 60 dB of rejection, for previews
+    Medium,         // about 90 dB
+    #[default]
+    High,           // about 110 dB, flat to 20 kHz at 44.1 kHz
+}
+
+impl Quality {
+    // stopband attenuation the filter is designed for, in dB, with a little margin
+    fn attenuation(self) -> f64 {
+        match self {
+            Self::Fast => 65.,
+            Self::Medium => 95.,
+            Self::High => 115.,
+        }
+    }
+}
+
+// polyphase windowed sinc converter between any two rates, for interleaved audio
+// fed in blocks of any size, output lines up with the input once flushed
+pub struct Resampler {
+    channels: usize,
+    up: u64,                // output rate over input rate, reduced
+    down: u64,
+    half: usize,            // input samples either side of the kernel's centre
+    table: Vec<f32>,        // (PHASES + 1) kernels of 2 * half taps
+    buffer: Vec<f32>,       // input frames from `start` on, interleaved
+    start: i64,             // index of the first buffered frame, negative for the zero padding
+    index: i64,             // input frame at or before the next output
+    remainder: u64,         // the next output's offset from `index`, in 1 / up of a frame
+    frames_in: i64,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32, quality: Quality, channels: usize) -> Result<Self> {
+        if from == 0 || to == 0 {
+            let msg = format!("can't resample from {from} Hz to {to} Hz");
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into());
+        }
+        let divisor = gcd(from as u64, to as u64);
+
+        // kaiser's design formulas, with the transition band between the passband and the lower of the two nyquist
+        // frequencies so nothing above it folds back, the cutoff sits halfway in cycles per input sample
+        let nyquist = 0.5 * (to as f64 / from as f64).min(1.);
+        let cutoff = nyquist * (PASSBAND + 1.) / 2.;
+        let transition = 2. * PI * nyquist * (1. - PASSBAND);
+        let attenuation = quality.attenuation();
+        let beta = 0.1102 * (attenuation - 8.7);
+        let half = ((attenuation - 7.95) / (2.285 * transition) / 2.).ceil() as usize;
+        let taps = 2 * half;
+
+        let mut table = vec![0.; (PHASES + 1) * taps];
+        for (phase, kernel) in table.chunks_exact_mut(taps).enumerate() {
+            let fraction = phase as f64 / PHASES as f64;
+            for (tap, coefficient) in kernel.iter_mut().enumerate() {
+                let x = tap as f64 + 1. - half as f64 - fraction;
+                *coefficient = (2. * cutoff * sinc(2. * cutoff * x) * kaiser(x / half as f64, beta)) as f32;
+            }
+            // every phase passes DC at unity gain
+            let sum: f32 = kernel.iter().sum();
+            kernel.iter_mut().for_each(|coefficient| *coefficient /= sum);
+        }
+
+        let mut resampler = Self {
+            channels: channels.max(1),
+            up: to as u64 / divisor,
+            down: from as u64 / divisor,
+            half,
+            table,
+            buffer: vec![],
+            start: 0,
+            index: 0,
+            remainder: 0,
+            frames_in: 0,
+        };
+        resampler.reset();
+        Ok(resampler)
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    // input frames the output lags behind while streaming
+    pub fn latency(&self) -> usize {
+        self.half
+    }
+
+    // forgets the input so far, as if newly created
+    pub fn reset(&mut self) {
+        let padding = self.half - 1;
+        self.buffer = vec![0.; padding * self.channels];
+        self.start = -(padding as i64);
+        self.index = 0;
+        self.remainder = 0;
+        self.frames_in = 0;
+    }
+
+    // appends every output frame the input so far allows to `output`
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.buffer.extend_from_slice(input);
+        self.frames_in += (input.len() / self.channels) as i64;
+        self.run(output, i64::MAX);
+    }
+
+    // pushes the end of the input through the filter, then resets
+    pub fn flush(&mut self, output: &mut Vec<f32>) {
+        let frames_in = self.frames_in;
+        self.buffer.resize(self.buffer.len() + self.half * self.channels, 0.);
+        self.run(output, frames_in);
+        self.reset();
+    }
+
+    // output frames up to input frame `end`
+    fn run(&mut self, output: &mut Vec<f32>, end: i64) {
+        let taps = 2 * self.half;
+        let buffered = (self.buffer.len() / self.channels) as i64;
+        while self.index < end && self.index + (self.half as i64) < self.start + buffered {
+            let position = self.remainder as f64 / self.up as f64 * PHASES as f64;
+            let phase = (position as usize).min(PHASES - 1);
+            let weight = (position - phase as f64) as f32;
+            let current = &self.table[phase * taps..(phase + 1) * taps];
+            let next = &self.table[(phase + 1) * taps..(phase + 2) * taps];
+
+            let first = (self.index + 1 - self.half as i64 - self.start) as usize;
+            for channel in 0..self.channels {
+                let mut sample = 0.;
+                for tap in 0..taps {
+                    let coefficient = current[tap] + (next[tap] - current[tap]) * weight;
+                    sample += self.buffer[(first + tap) * self.channels + channel] * coefficient;
+                }
+                output.push(sample);
+            }
+
+            self.remainder += self.down;
+            self.index += (self.remainder / self.up) as i64;
+            self.remainder %= self.up;
+        }
+
+        // frames no kernel will reach again
+        let used = (self.index + 1 - self.half as i64 - self.start).clamp(0, buffered);
+        self.buffer.drain(..used as usize * self.channels);
+        self.start += used;
+    }
+}
+
+// converts a whole interleaved recording at once, e.g. a sample recorded at another rate
+pub fn resample(input: &[f32], channels: usize, from: u32, to: u32, quality: Quality) -> Result<Vec<f32>> {
+    let mut resampler = Resampler::new(from, to, quality, channels)?;
+    let mut output = Vec::with_capacity((input.len() as u64 * to as u64 / from as u64) as usize + channels);
+    resampler.process(input, &mut output);
+    resampler.flush(&mut output);
+    Ok(output)
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0. { 1. } else { (PI * x).sin() / (PI * x) }
+}
+
+// x from -1 to 1
+fn kaiser(x: f64, beta: f64) -> f64 {
+    if x.abs() > 1. {
+        return 0.;
+    }
+    bessel_i0(beta * (1. - x * x).sqrt()) / bessel_i0(beta)
+}
+
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.;
+    let mut term = 1.;
+    let mut k = 1.;
+    while term > sum * 1e-12 {
+        term *= (x / (2. * k)) * (x / (2. * k));
+        sum += term;
+        k += 1.;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUALITIES: [(Quality, f64); 3] = [(Quality::Fast, 60.), (Quality::Medium, 90.), (Quality::High, 110.)];
+    const RATES: [(u32, u32); 2] = [(48000, 44100), (48000, 8000)];
+
+    fn sine(frequency: f64, rate: u32, len: usize) -> Vec<f32> {
+        (0..len).map(|n| (2. * PI * frequency * n as f64 / rate as f64).sin() as f32).collect()
+    }
+
+    // the middle of the output, clear of the filter's start and end
+    fn steady(output: &[f32], rate: u32) -> &[f32] {
+        let margin = rate as usize / 10;
+        &output[margin..output.len() - margin]
+    }
+
+    // least squares amplitude of a sine at `frequency` in `signal`
+    fn amplitude(signal: &[f32], frequency: f64, rate: u32) -> f64 {
+        let (mut ss, mut sc, mut cc, mut ys, mut yc) = (0., 0., 0., 0., 0.);
+        for (n, &y) in signal.iter().enumerate() {
+            let angle = 2. * PI * frequency * n as f64 / rate as f64;
+            let (s, c) = angle.sin_cos();
+            ss += s * s;
+            sc += s * c;
+            cc += c * c;
+            ys += y as f64 * s;
+            yc += y as f64 * c;
+        }
+        let determinant = ss * cc - sc * sc;
+        let a = (ys * cc - yc * sc) / determinant;
+        let b = (yc * ss - ys * sc) / determinant;
+        (a * a + b * b).sqrt()
+    }
+
+    #[test]
+    fn passband_is_flat() {
+        for (quality, _) in QUALITIES {
+            for (from, to) in RATES {
+                let nyquist = from.min(to) as f64 / 2.;
+                for fraction in [0.01, 0.1, 0.3, 0.5, 0.7, 0.8, 0.85, 0.9] {
+                    let frequency = fraction * nyquist;
+                    let output = resample(&sine(frequency, from, from as usize / 2), 1, from, to, quality).unwrap();
+                    let gain = 20. * amplitude(steady(&output, to), frequency, to).log10();
+                    assert!(gain.abs() <= 0.1, "{quality:?} {from} > {to} Hz: {gain:.3} dB at {frequency} Hz");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stopband_rejects_aliases() {
+        for (quality, rejection) in QUALITIES {
+            for (from, to) in RATES {
+                let nyquist = to as f64 / 2.;
+                let input_nyquist = from as f64 / 2.;
+                for fraction in [0.005, 0.1, 0.5, 0.9, 0.99] {
+                    // from just above the output's nyquist to just below the input's
+                    let frequency = nyquist + 50. + fraction * (input_nyquist - nyquist - 100.);
+                    let output = resample(&sine(frequency, from, from as usize / 2), 1, from, to, quality).unwrap();
+                    let output = steady(&output, to);
+                    // the input sine has an rms of 1 / sqrt(2), whatever comes out is aliasing
+                    let rms = (output.iter().map(|&sample| (sample as f64).powi(2)).sum::<f64>() / output.len() as f64).sqrt();
+                    let level = 20. * (rms * 2f64.sqrt()).log10();
+                    assert!(level < -rejection, "{quality:?} {from} > {to} Hz: alias of {frequency} Hz at {level:.1} dB");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zero_rates_are_an_error() {
+        assert!(Resampler::new(0, 44100, Quality::High, 1).is_err());
+        assert!(Resampler::new(48000, 0, Quality::High, 1).is_err());
+        assert!(resample(&[0.; 16], 1, 0, 8000, Quality::Fast).is_err());
+    }
+}
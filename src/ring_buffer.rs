@@ -0,0 +1,203 @@
+use std::{cell::UnsafeCell, mem::MaybeUninit, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
+
+// the slots and both ends, counters only ever grow and wrap around usize
+struct Shared<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    head: AtomicUsize,      // next slot to read, only moved by the consumer
+    tail: AtomicUsize,      // next slot to write, only moved by the producer
+}
+
+// each slot is touched by one side at a time, handed over through head and tail
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        for offset in 0..tail.wrapping_sub(head) {
+            let index = head.wrapping_add(offset) & self.mask;
+            unsafe { self.slots[index].get_mut().assume_init_drop() };
+        }
+    }
+}
+
+// single producer single consumer queue that never locks or allocates once created,
+// so the audio thread can read events without waiting on whoever sends them
+pub fn ring_buffer<T: Send>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(1).next_power_of_two();
+    let slots = (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect();
+    let shared = Arc::new(Shared {
+        slots,
+        mask: capacity - 1,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (Producer { shared: shared.clone() }, Consumer { shared })
+}
+
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Producer<T> {
+    // hands the value back when the queue is full
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(shared.head.load(Ordering::Acquire)) > shared.mask {
+            return Err(value);
+        }
+        unsafe { (*shared.slots[tail & shared.mask].get()).write(value) };
+        shared.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        let shared = &*self.shared;
+        shared.tail.load(Ordering::Relaxed).wrapping_sub(shared.head.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.mask + 1
+    }
+}
+
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        if head == shared.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = unsafe { (*shared.slots[head & shared.mask].get()).assume_init_read() };
+        shared.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        let shared = &*self.shared;
+        shared.tail.load(Ordering::Acquire).wrapping_sub(shared.head.load(Ordering::Relaxed))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.mask + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn push_fails_when_full() {
+        let (mut producer, mut consumer) = ring_buffer(3);
+        assert_eq!(producer.capacity(), 4);
+        for value in 0..4 {
+            assert_eq!(producer.push(value), Ok(()));
+        }
+        assert_eq!(producer.push(4), Err(4));
+        assert_eq!(consumer.len(), 4);
+
+        assert_eq!(consumer.pop(), Some(0));
+        assert_eq!(producer.push(4), Ok(()));
+        assert_eq!(producer.push(5), Err(5));
+    }
+
+    #[test]
+    fn fifo_across_the_wrap() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        let mut next_push = 0;
+        let mut next_pop = 0;
+        // three in, two out, so the ends keep moving round the slots
+        for _ in 0..20 {
+            while producer.len() < 3 {
+                producer.push(next_push).unwrap();
+                next_push += 1;
+            }
+            for _ in 0..2 {
+                assert_eq!(consumer.pop(), Some(next_pop));
+                next_pop += 1;
+            }
+        }
+        while let Some(value) = consumer.pop() {
+            assert_eq!(value, next_pop);
+            next_pop += 1;
+        }
+        assert_eq!(next_pop, next_push);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn moves_items_between_threads() {
+        const ITEMS: usize = 1_000_000;
+        let (mut producer, mut consumer) = ring_buffer(64);
+
+        let sender = thread::spawn(move || {
+            for mut value in 0..ITEMS {
+                while let Err(rejected) = producer.push(value) {
+                    value = rejected;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 0;
+        while expected < ITEMS {
+            match consumer.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        sender.join().unwrap();
+        assert_eq!(consumer.pop(), None);
+    }
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn drops_remaining_items_once() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (mut producer, mut consumer) = ring_buffer(4);
+
+        // leave the queued items straddling the wrap
+        for _ in 0..3 {
+            producer.push(Counted(drops.clone())).ok().unwrap();
+        }
+        drop(consumer.pop());
+        drop(consumer.pop());
+        for _ in 0..3 {
+            producer.push(Counted(drops.clone())).ok().unwrap();
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+
+        drop(producer);
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+        drop(consumer);
+        assert_eq!(drops.load(Ordering::Relaxed), 6);
+    }
+}
@@ -3,30 +3,239 @@ pub mod envelope;
 pub mod instrument;
 pub mod drum_machine;
 pub mod note;
+pub mod bus;
 pub mod effect;
+pub mod filter;
+pub mod fm;
+pub mod voice;
+pub mod wavetable;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, f32::consts::PI};
 
-use instrument::Instrument;
+use midly::MidiMessage;
+
+use bus::{chorus::Chorus, reverb::Reverb, EffectChain, DEFAULT_TEMPO};
+use instrument::{instrument_factory::{InstrumentFactory, DRUM_CHANNEL}, Instrument};
+use voice::StealStrategy;
+
+use crate::{audio_out::Channels, DEFAULT_SAMPLE_RATE};
+
+const CHANNELS: u8 = 16;
+const DEFAULT_MAX_VOICES: usize = 64;     // across every channel
 
 pub struct Synth {
     instruments: HashMap<u8, Instrument>, // Key is instrument's channel number
+    factory: InstrumentFactory,
+    max_voices: usize,
+    steal_strategy: StealStrategy,
+    inserts: HashMap<u8, EffectChain>,  // per channel, kept across program changes
+    reverb: Reverb,                     // shared send effects, fed by CC91 and CC93
+    chorus: Chorus,
+    master: EffectChain,
+    sample_rate: u32,
+    tempo: f32,                         // bpm, for effect chains made later
+    block: Vec<f32>,                    // one instrument's output while rendering
+    reverb_send: Vec<f32>,
+    chorus_send: Vec<f32>,
+}
+
+impl Default for Synth {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Synth {
+    // every channel starts on GM program 0, except for the drum kit on channel 10
     pub fn new() -> Self {
+        let factory = InstrumentFactory::new();
+        let instruments = (0..CHANNELS)
+            .map(|channel| (channel, factory.channel_default(channel)))
+            .collect();
+
         Synth {
-            instruments: HashMap::new(),
+            instruments,
+            factory,
+            max_voices: DEFAULT_MAX_VOICES,
+            steal_strategy: StealStrategy::ReleasedFirst,
+            inserts: HashMap::new(),
+            reverb: Reverb::new(0.5, 0.5, 1., 0.),
+            chorus: Chorus::new(0.015, 0.005, 0.8, 0., 1., 0.),
+            master: EffectChain::new(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            tempo: DEFAULT_TEMPO,
+            block: vec![],
+            reverb_send: vec![],
+            chorus_send: vec![],
+        }
+    }
+
+    // clears the effect tails, should be set to the output's rate before playing
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        for instrument in self.instruments.values_mut() {
+            instrument.set_sample_rate(sample_rate);
+        }
+        for chain in self.inserts.values_mut() {
+            chain.set_sample_rate(sample_rate);
         }
+        self.reverb.set_sample_rate(sample_rate);
+        self.chorus.set_sample_rate(sample_rate);
+        self.master.set_sample_rate(sample_rate);
     }
 
-    pub fn add_instrument(&mut self, channel: u8, instrument: Instrument) {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn add_instrument(&mut self, channel: u8, mut instrument: Instrument) {
+        instrument.set_sample_rate(self.sample_rate);
         self.instruments.insert(channel, instrument);
     }
 
-    pub fn note_on(&mut self, channel: u8, midi_note: u8) {
+    pub fn factory(&self) -> &InstrumentFactory {
+        &self.factory
+    }
+
+    pub fn factory_mut(&mut self) -> &mut InstrumentFactory {
+        &mut self.factory
+    }
+
+    pub fn set_max_voices(&mut self, max_voices: usize) {
+        self.max_voices = max_voices.max(1);
+    }
+
+    pub fn max_voices(&self) -> usize {
+        self.max_voices
+    }
+
+    pub fn set_steal_strategy(&mut self, steal_strategy: StealStrategy) {
+        self.steal_strategy = steal_strategy;
+    }
+
+    pub fn steal_strategy(&self) -> StealStrategy {
+        self.steal_strategy
+    }
+
+    pub fn active_voices(&self) -> usize {
+        self.instruments.values().map(|instr| instr.active_voices()).sum()
+    }
+
+    // no note is sounding anymore, though reverb and delays may still be ringing
+    pub fn is_idle(&self) -> bool {
+        self.instruments.values().all(|instr| instr.voices().is_empty())
+    }
+
+    // effects applied to one channel's mix before the sends
+    pub fn insert_effects_mut(&mut self, channel: u8) -> &mut EffectChain {
+        let (sample_rate, tempo) = (self.sample_rate, self.tempo);
+        self.inserts.entry(channel).or_insert_with(|| {
+            let mut chain = EffectChain::new();
+            chain.set_sample_rate(sample_rate);
+            chain.set_tempo(tempo);
+            chain
+        })
+    }
+
+    pub fn master_effects_mut(&mut self) -> &mut EffectChain {
+        &mut self.master
+    }
+
+    // the send effects only output their wet signal
+    pub fn reverb_mut(&mut self) -> &mut Reverb {
+        &mut self.reverb
+    }
+
+    pub fn chorus_mut(&mut self) -> &mut Chorus {
+        &mut self.chorus
+    }
+
+    // for tempo synced delays, including ones added later
+    pub fn set_tempo(&mut self, bpm: f32) {
+        self.tempo = bpm;
+        for chain in self.inserts.values_mut() {
+            chain.set_tempo(bpm);
+        }
+        self.master.set_tempo(bpm);
+    }
+
+    pub fn tempo(&self) -> f32 {
+        self.tempo
+    }
+
+    pub fn program_change(&mut self, channel: u8, program: u8) {
+        // GM drum channel keeps its kit
+        if channel == DRUM_CHANNEL {
+            return;
+        }
+        let mut instrument = self.factory.program(program);
+        instrument.set_sample_rate(self.sample_rate);
+        if let Some(old) = self.instruments.get(&channel) {
+            instrument.set_controllers(old.controllers());
+        }
+        self.instruments.insert(channel, instrument);
+    }
+
+    pub fn control_change(&mut self, channel: u8, controller: u8, value: u8) {
+        if let Some(instrument) = self.instruments.get_mut(&channel) {
+            instrument.control_change(controller, value);
+        }
+    }
+
+    pub fn pitch_bend(&mut self, channel: u8, bend: f32) {
+        if let Some(instrument) = self.instruments.get_mut(&channel) {
+            instrument.set_pitch_bend(bend);
+        }
+    }
+
+    pub fn channel_pressure(&mut self, channel: u8, pressure: f32) {
+        if let Some(instrument) = self.instruments.get_mut(&channel) {
+            instrument.set_channel_pressure(pressure);
+        }
+    }
+
+    pub fn key_pressure(&mut self, channel: u8, midi_note: u8, pressure: f32) {
         if let Some(instrument) = self.instruments.get_mut(&channel) {
-            instrument.note_on(midi_note);
+            instrument.set_key_pressure(midi_note, pressure);
+        }
+    }
+
+    pub fn note_on(&mut self, channel: u8, midi_note: u8, velocity: u8) {
+        if let Some(instrument) = self.instruments.get_mut(&channel) {
+            let voices = instrument.voices().len();
+            instrument.note_on(midi_note, velocity);
+            if instrument.voices().len() > voices {
+                self.limit_voices(channel);
+            }
+        }
+    }
+
+    // steals voices from any channel until the global limit is met, sparing the one `channel` just started
+    fn limit_voices(&mut self, channel: u8) {
+        while self.active_voices() > self.max_voices {
+            let mut selected: Option<(u8, usize)> = None;
+            for (&instr_channel, instrument) in &self.instruments {
+                let mut voices = instrument.voices();
+                if instr_channel == channel {
+                    voices = &voices[..voices.len().saturating_sub(1)];
+                }
+                let Some(index) = self.steal_strategy.select(voices) else {
+                    continue;
+                };
+                let better = selected.is_none_or(|(other_channel, other_index)| {
+                    self.steal_strategy.prefers(&voices[index], &self.instruments[&other_channel].voices()[other_index])
+                });
+                if better {
+                    selected = Some((instr_channel, index));
+                }
+            }
+
+            let Some((instr_channel, index)) = selected else {
+                break;
+            };
+            if let Some(instrument) = self.instruments.get_mut(&instr_channel) {
+                instrument.steal(index);
+            }
         }
     }
 
@@ -36,7 +245,124 @@ impl Synth {
         }
     }
 
+    // sends a channel message from any source, a file, live input or another thread
+    pub fn dispatch(&mut self, channel: u8, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOn { key, vel } => {
+                if vel == 0 {
+                    self.note_off(channel, key.as_int());
+                }
+                else {
+                    self.note_on(channel, key.as_int(), vel.as_int());
+                }
+            }
+            MidiMessage::NoteOff { key, .. } => {
+                self.note_off(channel, key.as_int());
+            }
+            MidiMessage::ProgramChange { program } => {
+                self.program_change(channel, program.as_int());
+            }
+            MidiMessage::Controller { controller, value } => {
+                self.control_change(channel, controller.as_int(), value.as_int());
+            }
+            MidiMessage::PitchBend { bend } => {
+                self.pitch_bend(channel, bend.as_f32());
+            }
+            MidiMessage::ChannelAftertouch { vel } => {
+                self.channel_pressure(channel, vel.as_int() as f32 / 127.);
+            }
+            MidiMessage::Aftertouch { key, vel } => {
+                self.key_pressure(channel, key.as_int(), vel.as_int() as f32 / 127.);
+            }
+        }
+    }
+
     pub fn next_sample(&mut self) -> f32 {
-        self.instruments.values_mut().map(|instr| instr.next_sample()).sum()
+        let mut buffer = [0.];
+        self.render(&mut buffer);
+        buffer[0]
+    }
+
+    // left and right, each channel placed by its pan controller
+    pub fn next_frame(&mut self) -> [f32; 2] {
+        let mut buffer = [0.; 2];
+        self.render_stereo(&mut buffer);
+        buffer
+    }
+
+    // fills `buffer` with the next mono samples, events in between blocks take effect on the first sample
+    pub fn render(&mut self, buffer: &mut [f32]) {
+        let frames = buffer.len();
+        self.prepare(frames, 1);
+        buffer.fill(0.);
+        for (channel, instrument) in &mut self.instruments {
+            instrument.render(&mut self.block);
+            let controllers = instrument.controllers();
+            let mut chain = self.inserts.get_mut(channel);
+            for (i, &sample) in self.block.iter().enumerate() {
+                let sample = match chain {
+                    Some(ref mut chain) => chain.process(sample),
+                    None => sample,
+                };
+                self.reverb_send[i] += sample * controllers.reverb_send;
+                self.chorus_send[i] += sample * controllers.chorus_send;
+                buffer[i] += sample;
+            }
+        }
+        for (i, sample) in buffer.iter_mut().enumerate() {
+            let mix = *sample + self.reverb.process(self.reverb_send[i]) + self.chorus.process(self.chorus_send[i]);
+            *sample = self.master.process(mix);
+        }
+    }
+
+    // same as `render` for interleaved left and right samples
+    pub fn render_stereo(&mut self, buffer: &mut [f32]) {
+        let frames = buffer.len() / 2;
+        self.prepare(frames, 2);
+        buffer.fill(0.);
+        for (channel, instrument) in &mut self.instruments {
+            instrument.render(&mut self.block);
+            let controllers = instrument.controllers();
+            let mut chain = self.inserts.get_mut(channel);
+            for (i, &sample) in self.block.iter().enumerate() {
+                let mut frame = pan(sample, controllers.pan);
+                if let Some(ref mut chain) = chain {
+                    frame = chain.process_stereo(frame);
+                }
+                for side in 0..2 {
+                    self.reverb_send[2 * i + side] += frame[side] * controllers.reverb_send;
+                    self.chorus_send[2 * i + side] += frame[side] * controllers.chorus_send;
+                    buffer[2 * i + side] += frame[side];
+                }
+            }
+        }
+        for (i, frame) in buffer.chunks_exact_mut(2).enumerate() {
+            let reverb = self.reverb.process_stereo([self.reverb_send[2 * i], self.reverb_send[2 * i + 1]]);
+            let chorus = self.chorus.process_stereo([self.chorus_send[2 * i], self.chorus_send[2 * i + 1]]);
+            let mix = [frame[0] + reverb[0] + chorus[0], frame[1] + reverb[1] + chorus[1]];
+            frame.copy_from_slice(&self.master.process_stereo(mix));
+        }
+    }
+
+    // `render` or `render_stereo`, whichever `channels` asks for
+    pub fn render_channels(&mut self, buffer: &mut [f32], channels: Channels) {
+        match channels {
+            Channels::Mono => self.render(buffer),
+            Channels::Stereo => self.render_stereo(buffer),
+        }
     }
-}
\ No newline at end of file
+
+    fn prepare(&mut self, frames: usize, channels: usize) {
+        self.block.resize(frames, 0.);
+        for send in [&mut self.reverb_send, &mut self.chorus_send] {
+            send.clear();
+            send.resize(frames * channels, 0.);
+        }
+    }
+}
+
+// equal power pan law, -3 dB on each side at the centre, pan from -1 (left) to 1 (right)
+fn pan(sample: f32, pan: f32) -> [f32; 2] {
+    let angle = (pan + 1.) * PI / 4.;
+    [sample * angle.cos(), sample * angle.sin()]
+}
@@ -0,0 +1,155 @@
+pub mod chorus;
+pub mod delay;
+pub mod reverb;
+
+use crate::DEFAULT_SAMPLE_RATE;
+
+pub const DEFAULT_TEMPO: f32 = 120.;       // bpm, until the song says otherwise
+
+use chorus::Chorus;
+use delay::Delay;
+use reverb::Reverb;
+
+// effects that run on a mixed signal rather than inside each note
+pub enum BusEffect {
+    Delay(Delay),
+    Chorus(Chorus),
+    Reverb(Reverb),
+}
+
+impl BusEffect {
+    pub fn process(&mut self, sample: f32) -> f32 {
+        match self {
+            Self::Delay(delay) => delay.process(sample),
+            Self::Chorus(chorus) => chorus.process(sample),
+            Self::Reverb(reverb) => reverb.process(sample),
+        }
+    }
+
+    pub fn process_stereo(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        match self {
+            Self::Delay(delay) => delay.process_stereo(frame),
+            Self::Chorus(chorus) => chorus.process_stereo(frame),
+            Self::Reverb(reverb) => reverb.process_stereo(frame),
+        }
+    }
+
+    pub fn set_tempo(&mut self, bpm: f32) {
+        if let Self::Delay(delay) = self {
+            delay.set_tempo(bpm);
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        match self {
+            Self::Delay(delay) => delay.set_sample_rate(sample_rate),
+            Self::Chorus(chorus) => chorus.set_sample_rate(sample_rate),
+            Self::Reverb(reverb) => reverb.set_sample_rate(sample_rate),
+        }
+    }
+}
+
+// effects applied one after the other
+pub struct EffectChain {
+    effects: Vec<BusEffect>,
+    sample_rate: u32,
+    tempo: f32,
+}
+
+impl Default for EffectChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EffectChain {
+    pub fn new() -> Self {
+        Self {
+            effects: vec![],
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            tempo: DEFAULT_TEMPO,
+        }
+    }
+
+    // effects pushed later are set to the chain's rate
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        for effect in &mut self.effects {
+            effect.set_sample_rate(sample_rate);
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn push(&mut self, mut effect: BusEffect) {
+        effect.set_sample_rate(self.sample_rate);
+        effect.set_tempo(self.tempo);
+        self.effects.push(effect);
+    }
+
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    pub fn effects_mut(&mut self) -> &mut [BusEffect] {
+        &mut self.effects
+    }
+
+    // effects pushed later are set to the chain's tempo too
+    pub fn set_tempo(&mut self, bpm: f32) {
+        self.tempo = bpm;
+        for effect in &mut self.effects {
+            effect.set_tempo(bpm);
+        }
+    }
+
+    pub fn tempo(&self) -> f32 {
+        self.tempo
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.effects.iter_mut().fold(sample, |sample, effect| effect.process(sample))
+    }
+
+    pub fn process_stereo(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        self.effects.iter_mut().fold(frame, |frame, effect| effect.process_stereo(frame))
+    }
+}
+
+// circular buffer read at fractional delays
+struct DelayLine {
+    buffer: Vec<f32>,
+    cursor: usize,
+}
+
+impl DelayLine {
+    fn new(max_samples: usize) -> Self {
+        Self {
+            buffer: vec![0.; max_samples.max(1) + 2],
+            cursor: 0,
+        }
+    }
+
+    // delay in samples, clamped to the buffer length
+    fn read(&self, delay: f32) -> f32 {
+        let len = self.buffer.len();
+        let delay = delay.clamp(1., (len - 2) as f32);
+        let whole = delay as usize;
+        let fraction = delay - whole as f32;
+        // the cursor holds the last sample written, one sample ago
+        let a = self.buffer[(self.cursor + len + 1 - whole) % len];
+        let b = self.buffer[(self.cursor + len - whole) % len];
+        a + (b - a) * fraction
+    }
+
+    fn write(&mut self, sample: f32) {
+        self.cursor = (self.cursor + 1) % self.buffer.len();
+        self.buffer[self.cursor] = sample;
+    }
+}
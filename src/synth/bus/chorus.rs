@@ -0,0 +1,87 @@
+use std::f32::consts::PI;
+
+use crate::{synth::bus::DelayLine, DEFAULT_SAMPLE_RATE};
+
+// a short delay swept by a sine LFO, a chorus with long delays and no feedback, a flanger with short ones and feedback
+pub struct Chorus {
+    lines: [DelayLine; 2],      // the second one is only used in stereo
+    delay: f32,         // seconds at the centre of the sweep
+    depth: f32,         // seconds the sweep moves either way
+    rate: f32,          // Hz
+    feedback: f32,
+    phase: f32,
+    wet: f32,
+    dry: f32,
+    sample_rate: f32,
+}
+
+impl Chorus {
+    pub fn new(delay: f32, depth: f32, rate: f32, feedback: f32, wet: f32, dry: f32) -> Self {
+        let depth = depth.min(delay);
+        Self {
+            lines: Self::lines(delay + depth, DEFAULT_SAMPLE_RATE as f32),
+            delay,
+            depth,
+            rate,
+            feedback,
+            phase: 0.,
+            wet,
+            dry,
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+        }
+    }
+
+    fn lines(longest: f32, sample_rate: f32) -> [DelayLine; 2] {
+        [(); 2].map(|_| DelayLine::new((longest * sample_rate) as usize + 1))
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f32;
+        self.lines = Self::lines(self.delay + self.depth, self.sample_rate);
+    }
+
+    pub fn ensemble() -> Self {
+        Self::new(0.015, 0.005, 0.8, 0., 0.5, 1.)
+    }
+
+    pub fn flanger() -> Self {
+        Self::new(0.002, 0.0018, 0.25, 0.7, 0.5, 1.)
+    }
+
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate;
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback;
+    }
+
+    pub fn set_mix(&mut self, wet: f32, dry: f32) {
+        self.wet = wet;
+        self.dry = dry;
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let output = self.process_side(0, sample, 0.);
+        self.advance();
+        output
+    }
+
+    // the right side sweeps a quarter cycle behind the left, widening the image
+    pub fn process_stereo(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let output = [self.process_side(0, frame[0], 0.), self.process_side(1, frame[1], 0.25)];
+        self.advance();
+        output
+    }
+
+    fn process_side(&mut self, side: usize, sample: f32, offset: f32) -> f32 {
+        let delay = self.delay + self.depth * (2. * PI * (self.phase + offset)).sin();
+        let delayed = self.lines[side].read(delay * self.sample_rate);
+        self.lines[side].write(sample + delayed * self.feedback);
+        sample * self.dry + delayed * self.wet
+    }
+
+    fn advance(&mut self) {
+        self.phase = (self.phase + self.rate / self.sample_rate) % 1.;
+    }
+}
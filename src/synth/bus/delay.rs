@@ -0,0 +1,95 @@
+use crate::{synth::bus::{DelayLine, DEFAULT_TEMPO}, DEFAULT_SAMPLE_RATE};
+
+const MAX_DELAY_TIME: f32 = 4.;     // seconds
+
+// feedback echo, either at a fixed time or a number of beats at the song's tempo
+pub struct Delay {
+    lines: [DelayLine; 2],      // the second one is only used in stereo
+    time: f32,                  // seconds
+    beats: Option<f32>,         // tempo sync, overrides the time once a tempo is known
+    feedback: f32,
+    damping: f32,               // one-pole lowpass coefficient in the feedback path, 0 for none
+    lowpass: [f32; 2],
+    wet: f32,
+    dry: f32,
+    sample_rate: f32,
+}
+
+impl Delay {
+    pub fn new(time: f32, feedback: f32, wet: f32, dry: f32) -> Self {
+        Self {
+            lines: Self::lines(DEFAULT_SAMPLE_RATE as f32),
+            time: time.clamp(0., MAX_DELAY_TIME),
+            beats: None,
+            feedback,
+            damping: 0.,
+            lowpass: [0.; 2],
+            wet,
+            dry,
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+        }
+    }
+
+    fn lines(sample_rate: f32) -> [DelayLine; 2] {
+        [(); 2].map(|_| DelayLine::new((MAX_DELAY_TIME * sample_rate) as usize))
+    }
+
+    // clears the echoes, the lines are resized for the new rate
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f32;
+        self.lines = Self::lines(self.sample_rate);
+        self.lowpass = [0.; 2];
+    }
+
+    // e.g. 0.75 for a dotted eighth, starts at 120 bpm until the tempo is set, pushing it on a chain sets the chain's
+    pub fn synced(beats: f32, feedback: f32, wet: f32, dry: f32) -> Self {
+        let mut delay = Self::new(0., feedback, wet, dry);
+        delay.beats = Some(beats);
+        delay.set_tempo(DEFAULT_TEMPO);
+        delay
+    }
+
+    pub fn set_tempo(&mut self, bpm: f32) {
+        if let Some(beats) = self.beats {
+            self.time = (beats * 60. / bpm).clamp(0., MAX_DELAY_TIME);
+        }
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time.clamp(0., MAX_DELAY_TIME);
+        self.beats = None;
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback;
+    }
+
+    // from 0 (bright repeats) to 1 (dull)
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping.clamp(0., 0.99);
+    }
+
+    pub fn set_mix(&mut self, wet: f32, dry: f32) {
+        self.wet = wet;
+        self.dry = dry;
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.process_side(0, sample)
+    }
+
+    pub fn process_stereo(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        [self.process_side(0, frame[0]), self.process_side(1, frame[1])]
+    }
+
+    fn process_side(&mut self, side: usize, sample: f32) -> f32 {
+        let echo = self.lines[side].read(self.time * self.sample_rate);
+        self.lowpass[side] += (echo - self.lowpass[side]) * (1. - self.damping);
+        self.lines[side].write(sample + self.lowpass[side] * self.feedback);
+        sample * self.dry + echo * self.wet
+    }
+}
@@ -0,0 +1,154 @@
+use crate::DEFAULT_SAMPLE_RATE;
+
+// freeverb tunings, in samples at 44.1 kHz
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const TUNING_RATE: f32 = 44100.;
+const INPUT_GAIN: f32 = 0.015;
+const ROOM_SCALE: f32 = 0.28;
+const ROOM_OFFSET: f32 = 0.7;
+const DAMPING_SCALE: f32 = 0.4;
+const ALLPASS_FEEDBACK: f32 = 0.5;
+const STEREO_SPREAD: usize = 23;        // extra samples on the right channel's delays
+
+// feedback comb with a lowpass in its loop
+struct Comb {
+    buffer: Vec<f32>,
+    cursor: usize,
+    store: f32,
+}
+
+impl Comb {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.; len.max(1)],
+            cursor: 0,
+            store: 0.,
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.cursor];
+        self.store = output * (1. - damping) + self.store * damping;
+        self.buffer[self.cursor] = input + self.store * feedback;
+        self.cursor = (self.cursor + 1) % self.buffer.len();
+        output
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    cursor: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.; len.max(1)],
+            cursor: 0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.cursor];
+        self.buffer[self.cursor] = input + buffered * ALLPASS_FEEDBACK;
+        self.cursor = (self.cursor + 1) % self.buffer.len();
+        buffered - input
+    }
+}
+
+// one channel's combs and allpasses, `spread` lengthens every delay to decorrelate the channels
+struct Tank {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+impl Tank {
+    fn new(spread: usize, sample_rate: u32) -> Self {
+        let scale = sample_rate as f32 / TUNING_RATE;
+        Self {
+            combs: COMB_TUNINGS.iter().map(|&len| Comb::new(((len + spread) as f32 * scale) as usize)).collect(),
+            allpasses: ALLPASS_TUNINGS.iter().map(|&len| Allpass::new(((len + spread) as f32 * scale) as usize)).collect(),
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let mut output: f32 = self.combs.iter_mut().map(|comb| comb.process(input, feedback, damping)).sum();
+        for allpass in &mut self.allpasses {
+            output = allpass.process(output);
+        }
+        output
+    }
+}
+
+// schroeder-moorer reverb as in freeverb: parallel damped combs into series allpasses
+pub struct Reverb {
+    tanks: [Tank; 2],       // the second one is only used in stereo
+    room_size: f32,
+    damping: f32,
+    wet: f32,
+    dry: f32,
+}
+
+impl Reverb {
+    // room size and damping from 0 to 1
+    pub fn new(room_size: f32, damping: f32, wet: f32, dry: f32) -> Self {
+        Self {
+            tanks: Self::tanks(DEFAULT_SAMPLE_RATE),
+            room_size: room_size.clamp(0., 1.),
+            damping: damping.clamp(0., 1.),
+            wet,
+            dry,
+        }
+    }
+
+    fn tanks(sample_rate: u32) -> [Tank; 2] {
+        [Tank::new(0, sample_rate), Tank::new(STEREO_SPREAD, sample_rate)]
+    }
+
+    // clears the tail, the delays are rescaled for the new rate
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.tanks = Self::tanks(sample_rate);
+    }
+
+    pub fn set_room_size(&mut self, room_size: f32) {
+        self.room_size = room_size.clamp(0., 1.);
+    }
+
+    pub fn room_size(&self) -> f32 {
+        self.room_size
+    }
+
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping.clamp(0., 1.);
+    }
+
+    pub fn damping(&self) -> f32 {
+        self.damping
+    }
+
+    pub fn set_mix(&mut self, wet: f32, dry: f32) {
+        self.wet = wet;
+        self.dry = dry;
+    }
+
+    fn coefficients(&self) -> (f32, f32) {
+        (self.room_size * ROOM_SCALE + ROOM_OFFSET, self.damping * DAMPING_SCALE)
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let (feedback, damping) = self.coefficients();
+        let output = self.tanks[0].process(sample * INPUT_GAIN, feedback, damping);
+        // freeverb's wet scale of 3 brings the tail back to the input's level
+        sample * self.dry + output * 3. * self.wet
+    }
+
+    // both tanks are fed the same mono sum, their different lengths spread the tail across the image
+    pub fn process_stereo(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let (feedback, damping) = self.coefficients();
+        let input = (frame[0] + frame[1]) / 2. * INPUT_GAIN;
+        let left = self.tanks[0].process(input, feedback, damping);
+        let right = self.tanks[1].process(input, feedback, damping);
+        [frame[0] * self.dry + left * 3. * self.wet, frame[1] * self.dry + right * 3. * self.wet]
+    }
+}
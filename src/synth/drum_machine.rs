@@ -1,4 +1,4 @@
-use crate::synth::{effect:: Effect, envelope::{Envelope, EnvelopeShape}, note::Note, oscillator::{Oscillator, Waveform}};
+use crate::synth::{effect:: Effect, envelope::{Envelope, EnvelopeShape}, fm::{Algorithm, FmPatch, Operator}, note::Note, oscillator::{Oscillator, Waveform}};
 
 pub struct DrumMachine {
 
@@ -29,6 +29,21 @@ impl DrumMachine {
         Note::from_env(waveform, 2000., 0.005, lfo, envelope, Some(envelope), 1., effects)
     }
 
+    // two fm pairs at an inharmonic interval give the metallic clang
+    pub fn cowbell() -> Note {
+        let decay = Envelope::new(0., 0.3, 0., 0., EnvelopeShape::Exponential);
+        let operators = vec![
+            Operator::new(1., 0.5, decay),
+            Operator::new(1.41, 2., decay),
+            Operator::new(1.48, 0.5, decay),
+            Operator::new(2.1, 2., decay),
+        ];
+        let patch = FmPatch::new(Algorithm::TwoStacks, operators, 0.2);
+        let envelope = Envelope::new(0., 0., 1., 0.05, EnvelopeShape::Exponential);
+        let lfo = Oscillator::new(Waveform::Sine, 1.);
+        Note::from_fm(&patch, 560., 0., lfo, envelope, None, 0., vec![])
+    }
+
     pub fn cymbal() -> Note {
         let waveform = Waveform::Sine;
         let envelope = Envelope::new(0., 2., 0., 0., EnvelopeShape::Exponential);
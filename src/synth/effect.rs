@@ -1,6 +1,8 @@
 use num_traits::Pow;
 
-#[derive(Clone, Copy, Debug)]
+use crate::synth::filter::Filter;
+
+#[derive(Clone, Debug)]
 pub enum Effect {
     Gain(f32),
     HardClip(f32),
@@ -9,11 +11,12 @@ pub enum Effect {
     InfiniteClip(f32),
     BitCrusher(u32),
     Fangs(f32),
+    Filter(Filter),     // fixed cutoff, keeps its state between samples
 }
 
 impl Effect {
-    pub fn apply(self, sample: f32) -> f32 {
-        match self {
+    pub fn apply(&mut self, sample: f32) -> f32 {
+        match *self {
             Self::Gain(level) => gain(sample, level),
             Self::HardClip(threshold) => hard_clip(sample, threshold),
             Self::SoftCubic(threshold) => soft_cubic(sample, threshold),
@@ -21,6 +24,13 @@ impl Effect {
             Self::InfiniteClip(amplitude) => infinite_clip(sample, amplitude),
             Self::BitCrusher(bits) => bit_crusher(sample, bits),
             Self::Fangs(threshold) => fangs(sample, threshold),
+            Self::Filter(ref mut filter) => filter.process(sample),
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        if let Self::Filter(filter) = self {
+            filter.set_sample_rate(sample_rate);
         }
     }
 }
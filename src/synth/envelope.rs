@@ -1,6 +1,6 @@
 use std::f32::consts::LN_2;
 
-use crate::SAMPLE_RATE;
+use crate::DEFAULT_SAMPLE_RATE;
 
 #[derive(Clone, Copy, Debug)]
 pub enum EnvelopeShape {
@@ -49,7 +49,9 @@ pub struct Envelope {
     shape: EnvelopeShape,
     state: EnvelopeState,
     level: f32,
+    start_level: f32,       // level the attack starts from, non zero when retriggered while sounding
     time: f32,
+    sample_rate: f32,
 }
 
 impl Envelope {
@@ -62,15 +64,34 @@ impl Envelope {
             shape,
             state: EnvelopeState::Idle,
             level: 0.,
+            start_level: 0.,
             time: 0.,
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
         }
     }
 
+    pub fn set_attack(&mut self, attack: f32) {
+        self.attack = attack;
+    }
+
+    pub fn attack(&self) -> f32 {
+        self.attack
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f32;
+    }
+
     pub fn state(&self) -> EnvelopeState {
         self.state
     }
 
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
     pub fn trigger(&mut self) {
+        self.start_level = self.level;
         self.state = EnvelopeState::Attack;
         self.time = 0.;
     }
@@ -82,14 +103,21 @@ impl Envelope {
         }
     }
 
+    // silences the envelope without a release stage
+    pub fn stop(&mut self) {
+        self.state = EnvelopeState::Idle;
+        self.level = 0.;
+    }
+
     pub fn get_level(&mut self) -> f32 {
-        self.time += 1. / SAMPLE_RATE as f32;
+        self.time += 1. / self.sample_rate;
         match self.state {
             EnvelopeState::Idle => {
                 self.level = 0.;
             }
             EnvelopeState::Attack => {
-                self.level = self.shape.attack(self.time, self.attack).min(1.);
+                let attack = self.shape.attack(self.time, self.attack).min(1.);
+                self.level = self.start_level + (1. - self.start_level) * attack;
                 if self.time >= self.attack {
                     self.state = EnvelopeState::Decay;
                     self.time = 0.;
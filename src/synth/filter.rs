@@ -0,0 +1,228 @@
+use std::f32::consts::PI;
+
+use crate::{synth::envelope::Envelope, DEFAULT_SAMPLE_RATE};
+
+const MAX_RESONANCE: f32 = 0.98;        // keeps the filter just short of self oscillation
+const BUTTERWORTH_DAMPING: f32 = std::f32::consts::SQRT_2;
+const KEY_TRACKING_CENTER: f32 = 261.63;    // C4, where key tracking leaves the cutoff alone
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterMode {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterSlope {
+    Db12,
+    Db24,
+}
+
+// one trapezoidal state variable section, 12 dB per octave
+#[derive(Clone, Copy, Debug, Default)]
+struct Section {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+impl Section {
+    fn process(&mut self, input: f32, mode: FilterMode, g: f32, k: f32) -> f32 {
+        let a1 = 1. / (1. + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+        let v3 = input - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = 2. * v1 - self.ic1eq;
+        self.ic2eq = 2. * v2 - self.ic2eq;
+
+        match mode {
+            FilterMode::LowPass => v2,
+            FilterMode::HighPass => input - k * v1 - v2,
+            FilterMode::BandPass => k * v1,     // unity gain at the cutoff
+            FilterMode::Notch => input - k * v1,
+        }
+    }
+}
+
+// resonant state variable filter, stays stable while its cutoff is modulated every sample
+#[derive(Clone, Copy, Debug)]
+pub struct Filter {
+    mode: FilterMode,
+    slope: FilterSlope,
+    cutoff: f32,
+    resonance: f32,
+    g: f32,
+    sections: [Section; 2],
+    sample_rate: f32,
+}
+
+impl Filter {
+    pub fn new(mode: FilterMode, slope: FilterSlope, cutoff: f32, resonance: f32) -> Self {
+        let mut filter = Self {
+            mode,
+            slope,
+            cutoff: 0.,
+            resonance: resonance.clamp(0., MAX_RESONANCE),
+            g: 0.,
+            sections: [Section::default(); 2],
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+        };
+        filter.set_cutoff(cutoff);
+        filter
+    }
+
+    pub fn set_cutoff(&mut self, cutoff: f32) {
+        let cutoff = cutoff.clamp(10., 0.49 * self.sample_rate);
+        if cutoff != self.cutoff {
+            self.cutoff = cutoff;
+            self.g = (PI * cutoff / self.sample_rate).tan();
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let cutoff = self.cutoff;
+        self.sample_rate = sample_rate as f32;
+        self.cutoff = 0.;
+        self.set_cutoff(cutoff);
+    }
+
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+
+    // from 0 (no peak) to 1 (ringing)
+    pub fn set_resonance(&mut self, resonance: f32) {
+        self.resonance = resonance.clamp(0., MAX_RESONANCE);
+    }
+
+    pub fn resonance(&self) -> f32 {
+        self.resonance
+    }
+
+    pub fn mode(&self) -> FilterMode {
+        self.mode
+    }
+
+    pub fn slope(&self) -> FilterSlope {
+        self.slope
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let k = 2. - 2. * self.resonance;
+        match self.slope {
+            FilterSlope::Db12 => self.sections[0].process(sample, self.mode, self.g, k),
+            FilterSlope::Db24 => {
+                // only the second section resonates, so the peak doesn't double
+                let sample = self.sections[0].process(sample, self.mode, self.g, BUTTERWORTH_DAMPING);
+                self.sections[1].process(sample, self.mode, self.g, k)
+            }
+        }
+    }
+}
+
+// a voice's filter with its own envelope, cutoff following the note's pitch and velocity
+#[derive(Clone, Copy, Debug)]
+pub struct FilterStage {
+    filter: Filter,
+    cutoff: f32,                // before modulation
+    envelope: Envelope,
+    envelope_amount: f32,       // octaves added to the cutoff at the envelope's peak
+    resonance: f32,             // before modulation
+    resonance_amount: f32,      // added to the resonance at the envelope's peak, negative to damp the attack
+    key_tracking: f32,          // 0 keeps the cutoff fixed, 1 moves it with the pitch
+    velocity_amount: f32,       // octaves taken off the cutoff at the lowest velocity
+    velocity: f32,
+}
+
+impl FilterStage {
+    pub fn new(filter: Filter, envelope: Envelope, envelope_amount: f32) -> Self {
+        Self {
+            cutoff: filter.cutoff(),
+            resonance: filter.resonance(),
+            filter,
+            envelope,
+            envelope_amount,
+            resonance_amount: 0.,
+            key_tracking: 0.,
+            velocity_amount: 0.,
+            velocity: 1.,
+        }
+    }
+
+    pub fn set_key_tracking(&mut self, key_tracking: f32) {
+        self.key_tracking = key_tracking;
+    }
+
+    pub fn key_tracking(&self) -> f32 {
+        self.key_tracking
+    }
+
+    pub fn set_velocity_amount(&mut self, octaves: f32) {
+        self.velocity_amount = octaves;
+    }
+
+    pub fn velocity_amount(&self) -> f32 {
+        self.velocity_amount
+    }
+
+    pub fn set_cutoff(&mut self, cutoff: f32) {
+        self.cutoff = cutoff;
+    }
+
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+
+    pub fn set_resonance(&mut self, resonance: f32) {
+        self.resonance = resonance.clamp(0., MAX_RESONANCE);
+        self.filter.set_resonance(resonance);
+    }
+
+    pub fn resonance(&self) -> f32 {
+        self.resonance
+    }
+
+    pub fn set_resonance_amount(&mut self, resonance_amount: f32) {
+        self.resonance_amount = resonance_amount;
+    }
+
+    pub fn resonance_amount(&self) -> f32 {
+        self.resonance_amount
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.filter.set_sample_rate(sample_rate);
+        self.envelope.set_sample_rate(sample_rate);
+    }
+
+    // velocity from 0 to 1
+    pub fn set_velocity(&mut self, velocity: f32) {
+        self.velocity = velocity;
+    }
+
+    pub fn trigger(&mut self) {
+        self.envelope.trigger();
+    }
+
+    pub fn release(&mut self) {
+        self.envelope.release();
+    }
+
+    // frequency is the note's current pitch, for key tracking
+    pub fn process(&mut self, sample: f32, frequency: f32) -> f32 {
+        let level = self.envelope.get_level();
+        let mut octaves = level * self.envelope_amount;
+        octaves += (self.velocity - 1.) * self.velocity_amount;
+        if frequency > 0. {
+            octaves += self.key_tracking * (frequency / KEY_TRACKING_CENTER).log2();
+        }
+        self.filter.set_cutoff(self.cutoff * 2f32.powf(octaves));
+        if self.resonance_amount != 0. {
+            self.filter.set_resonance(self.resonance + level * self.resonance_amount);
+        }
+        self.filter.process(sample)
+    }
+}
@@ -0,0 +1,202 @@
+use std::f32::consts::PI;
+
+use crate::synth::{envelope::{Envelope, EnvelopeState}, oscillator::{Oscillator, Waveform}};
+
+pub const OPERATORS: usize = 4;
+
+// routings of four operators, numbered 1 to 4 as on DX style synths, operator 4 has the feedback loop
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Stack,              // 4 > 3 > 2 > 1
+    Branch,             // (3 + 4) > 2 > 1
+    Tree,               // (2 + (4 > 3)) > 1
+    TwoStacks,          // 2 > 1, 4 > 3
+    SharedModulator,    // 4 > (1, 2, 3)
+    StackAndCarrier,    // 4 > 3 > 2, 1
+    OneModulated,       // 4 > 3, 2, 1
+    Additive,           // 1, 2, 3, 4
+}
+
+impl Algorithm {
+    // (modulator, target) pairs by index, modulators always come after their targets
+    fn routes(self) -> &'static [(usize, usize)] {
+        match self {
+            Self::Stack => &[(3, 2), (2, 1), (1, 0)],
+            Self::Branch => &[(3, 1), (2, 1), (1, 0)],
+            Self::Tree => &[(3, 2), (2, 0), (1, 0)],
+            Self::TwoStacks => &[(1, 0), (3, 2)],
+            Self::SharedModulator => &[(3, 0), (3, 1), (3, 2)],
+            Self::StackAndCarrier => &[(3, 2), (2, 1)],
+            Self::OneModulated => &[(3, 2)],
+            Self::Additive => &[],
+        }
+    }
+
+    // operators that are heard
+    fn carriers(self) -> &'static [usize] {
+        match self {
+            Self::Stack | Self::Branch | Self::Tree => &[0],
+            Self::TwoStacks => &[0, 2],
+            Self::SharedModulator | Self::OneModulated => &[0, 1, 2],
+            Self::StackAndCarrier => &[0, 1],
+            Self::Additive => &[0, 1, 2, 3],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Operator {
+    ratio: f32,             // frequency relative to the note
+    detune: f32,            // Hz added on top of the ratio, for slow beating
+    level: f32,             // output level as a carrier, peak phase deviation in radians as a modulator
+    envelope: Envelope,
+}
+
+impl Operator {
+    pub fn new(ratio: f32, level: f32, envelope: Envelope) -> Self {
+        Self {
+            ratio,
+            detune: 0.,
+            level,
+            envelope,
+        }
+    }
+
+    pub fn set_detune(&mut self, detune: f32) {
+        self.detune = detune;
+    }
+
+    pub fn detune(&self) -> f32 {
+        self.detune
+    }
+
+    pub fn ratio(&self) -> f32 {
+        self.ratio
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+}
+
+// the operators of an FM instrument and how they're wired, up to OPERATORS of them
+#[derive(Clone, Debug)]
+pub struct FmPatch {
+    algorithm: Algorithm,
+    operators: Vec<Operator>,
+    feedback: f32,          // how much operator 4 modulates itself, in radians
+}
+
+impl FmPatch {
+    pub fn new(algorithm: Algorithm, operators: Vec<Operator>, feedback: f32) -> Self {
+        let mut operators = operators;
+        operators.truncate(OPERATORS);
+        Self {
+            algorithm,
+            operators,
+            feedback,
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn operators(&self) -> &[Operator] {
+        &self.operators
+    }
+
+    pub fn feedback(&self) -> f32 {
+        self.feedback
+    }
+}
+
+struct OperatorState {
+    oscillator: Oscillator,
+    envelope: Envelope,
+    ratio: f32,
+    detune: f32,
+    level: f32,
+}
+
+// a playing FM note
+pub struct FmVoice {
+    operators: Vec<OperatorState>,
+    algorithm: Algorithm,
+    feedback: f32,
+    history: [f32; 2],      // last outputs of operator 4, averaged to keep feedback stable
+}
+
+impl FmVoice {
+    pub fn new(patch: &FmPatch, frequency: f32) -> Self {
+        let operators = patch.operators.iter()
+            .map(|operator| OperatorState {
+                oscillator: Oscillator::new(Waveform::Sine, frequency * operator.ratio + operator.detune),
+                envelope: operator.envelope,
+                ratio: operator.ratio,
+                detune: operator.detune,
+                level: operator.level,
+            })
+            .collect();
+        Self {
+            operators,
+            algorithm: patch.algorithm,
+            feedback: patch.feedback,
+            history: [0.; 2],
+        }
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        for operator in &mut self.operators {
+            operator.oscillator.set_frequency(frequency * operator.ratio + operator.detune);
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        for operator in &mut self.operators {
+            operator.oscillator.set_sample_rate(sample_rate);
+            operator.envelope.set_sample_rate(sample_rate);
+        }
+    }
+
+    pub fn note_on(&mut self) {
+        for operator in &mut self.operators {
+            operator.envelope.trigger();
+        }
+    }
+
+    pub fn note_off(&mut self) {
+        for operator in &mut self.operators {
+            operator.envelope.release();
+        }
+    }
+
+    // every carrier has gone silent
+    pub fn is_finished(&self) -> bool {
+        self.algorithm.carriers().iter()
+            .filter_map(|&carrier| self.operators.get(carrier))
+            .all(|operator| matches!(operator.envelope.state(), EnvelopeState::Idle))
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let mut outputs = [0.; OPERATORS];
+        let routes = self.algorithm.routes();
+        let last = OPERATORS - 1;
+
+        for (index, operator) in self.operators.iter_mut().enumerate().rev() {
+            let mut modulation: f32 = routes.iter()
+                .filter(|&&(_, target)| target == index)
+                .map(|&(modulator, _)| outputs[modulator])
+                .sum();
+            if index == last {
+                modulation += self.feedback * (self.history[0] + self.history[1]) / 2.;
+            }
+
+            let level = operator.envelope.get_level() * operator.level;
+            outputs[index] = operator.oscillator.modulated_sample(modulation / (2. * PI)) * level;
+        }
+        self.history = [outputs[last], self.history[0]];
+
+        self.algorithm.carriers().iter().map(|&carrier| outputs[carrier]).sum()
+    }
+}
@@ -1,85 +1,641 @@
-use std::collections::HashMap;
+pub mod instrument_factory;
 
-use crate::synth::{effect::Effect, envelope::{Envelope, EnvelopeShape, EnvelopeState}, note::Note, oscillator::{Oscillator, Waveform}, drum_machine::DrumMachine};
+use std::{collections::HashSet, sync::Arc};
+
+use crate::{synth::{effect::Effect, envelope::{Envelope, EnvelopeShape}, filter::{Filter, FilterMode, FilterSlope, FilterStage}, fm::{Algorithm, FmPatch, Operator}, note::Note, oscillator::{stack::{LayerModulation, OscillatorLayer}, Oscillator, Waveform}, drum_machine::DrumMachine, voice::{StealStrategy, Voice}, wavetable::{Morph, Wavetable}}, DEFAULT_SAMPLE_RATE};
 
 pub enum InstrumentKind {
     Melodic,
     Percussive,
+    Fm(FmPatch),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InstrumentMode {
-    Legato,
-    Monophonic,
+    Legato,         // monophonic, overlapping notes only change pitch
+    Monophonic,     // monophonic, every note retriggers the envelopes
     Polyphonic,
 }
 
+// which held note sounds in the monophonic modes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotePriority {
+    Last,
+    Low,
+    High,
+}
+
+impl NotePriority {
+    fn select(self, held: &[u8]) -> Option<u8> {
+        match self {
+            Self::Last => held.last().copied(),
+            Self::Low => held.iter().min().copied(),
+            Self::High => held.iter().max().copied(),
+        }
+    }
+}
+
+const MODULATION_DEPTH: f32 = 0.03;     // extra vibrato depth at full modulation wheel
+const AFTERTOUCH_GAIN: f32 = 0.5;       // extra gain at full pressure
+const RPN_NULL: (u8, u8) = (127, 127);
+const RPN_BEND_RANGE: (u8, u8) = (0, 0);
+
+const VELOCITY_ATTACK_TIME: f32 = 0.05;  // extra attack time for the softest notes at full sensitivity
+const DEFAULT_MAX_VOICES: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+pub enum VelocityCurve {
+    Linear,
+    Exponential(f32),       // exponent, 2 follows the GM recommendation
+    Fixed(f32),             // level used for every note
+}
+
+impl VelocityCurve {
+    pub fn level(self, velocity: u8) -> f32 {
+        let velocity = velocity as f32 / 127.;
+        match self {
+            Self::Linear => velocity,
+            Self::Exponential(exponent) => velocity.powf(exponent),
+            Self::Fixed(level) => level,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AftertouchTarget {
+    None,
+    Volume,
+    LfoDepth,
+}
+
+// channel controller state, kept across program changes
+#[derive(Clone, Copy, Debug)]
+pub struct Controllers {
+    pub volume: f32,
+    pub expression: f32,
+    pub modulation: f32,
+    pub sustain: bool,
+    pub pitch_bend: f32,        // -1 to 1
+    pub bend_range: f32,        // semitones at full bend
+    pub pressure: f32,          // channel aftertouch
+    pub rpn: (u8, u8),          // registered parameter selected for data entry, (MSB, LSB)
+    pub pan: f32,               // CC10, -1 (left) to 1 (right), only heard in stereo
+    pub reverb_send: f32,       // CC91, level sent to the synth's reverb
+    pub chorus_send: f32,       // CC93, level sent to the synth's chorus
+}
+
+impl Default for Controllers {
+    fn default() -> Self {
+        Self {
+            volume: 100. / 127.,
+            expression: 1.,
+            modulation: 0.,
+            sustain: false,
+            pitch_bend: 0.,
+            bend_range: 2.,
+            pressure: 0.,
+            rpn: RPN_NULL,
+            pan: 0.,
+            reverb_send: 0.,
+            chorus_send: 0.,
+        }
+    }
+}
+
+impl Controllers {
+    // squared curves, as GM recommends for CC7 and CC11
+    pub fn gain(&self) -> f32 {
+        (self.volume * self.expression).powi(2)
+    }
+
+    pub fn bend_semitones(&self) -> f32 {
+        self.pitch_bend * self.bend_range
+    }
+}
+
 pub struct Instrument {
     kind: InstrumentKind,
-    waveform: Waveform,
-    // oscillators: Vec<Oscillator>,        TODO: substitute single waveform for this
+    mode: InstrumentMode,
+    priority: NotePriority,
+    glide: f32,                 // portamento time in seconds
+    oscillators: Vec<OscillatorLayer>,
     lfo: Oscillator,
     lfo_amplitude: f32,
+    morph: Option<Morph>,       // wavetable position modulation
     amp_envelope: Envelope,
     freq_envelope: Option<Envelope>,
     effects: Vec<Effect>,
+    filter: Option<FilterStage>,
     volume: f32,
-    notes: HashMap<u8, Note>, // Key is MIDI note number
+    velocity_curve: VelocityCurve,
+    velocity_brightness: f32,   // 0 to 1, how much soft notes are darkened
+    velocity_attack: f32,       // 0 to 1, how much soft notes are slowed down
+    aftertouch: AftertouchTarget,
+    controllers: Controllers,
+    voices: Vec<Voice>,       // oldest first, a key may have a held voice and several release tails
+    max_voices: usize,
+    steal_strategy: StealStrategy,
+    sustained: HashSet<u8>,   // notes released while the sustain pedal is down
+    held: Vec<u8>,            // keys down in the monophonic modes, oldest first
+    mono_key: Option<u8>,     // key of the single voice in the monophonic modes
+    mono_velocity: u8,
+    sample_rate: u32,
+    block: Vec<f32>,          // one voice's output while rendering
 }
 
 impl Instrument {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(kind: InstrumentKind, waveform: Waveform, lfo: Oscillator, lfo_amplitude: f32, amp_envelope: Envelope, freq_envelope: Option<Envelope>, volume: f32, effects: Vec<Effect>) -> Self {
         Self {
             kind,
-            waveform,
+            mode: InstrumentMode::Polyphonic,
+            priority: NotePriority::Last,
+            glide: 0.,
+            oscillators: vec![OscillatorLayer::new(waveform)],
             lfo,
             lfo_amplitude,
+            morph: None,
             amp_envelope,
             freq_envelope,
             effects,
+            filter: None,
             volume,
-            notes: HashMap::new(),
+            velocity_curve: VelocityCurve::Linear,
+            velocity_brightness: 0.,
+            velocity_attack: 0.,
+            aftertouch: AftertouchTarget::Volume,
+            controllers: Controllers::default(),
+            voices: vec![],
+            max_voices: DEFAULT_MAX_VOICES,
+            steal_strategy: StealStrategy::ReleasedFirst,
+            sustained: HashSet::new(),
+            held: vec![],
+            mono_key: None,
+            mono_velocity: 0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            block: vec![],
+        }
+    }
+
+    // applies to the voices already playing as well as new ones
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        for voice in &mut self.voices {
+            voice.note_mut().set_sample_rate(sample_rate);
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_mode(&mut self, mode: InstrumentMode) {
+        if mode != self.mode {
+            self.all_notes_off();
+        }
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> InstrumentMode {
+        self.mode
+    }
+
+    pub fn set_priority(&mut self, priority: NotePriority) {
+        self.priority = priority;
+    }
+
+    pub fn priority(&self) -> NotePriority {
+        self.priority
+    }
+
+    pub fn set_glide(&mut self, glide: f32) {
+        self.glide = glide;
+    }
+
+    pub fn glide(&self) -> f32 {
+        self.glide
+    }
+
+    // replaces the instrument's single waveform with a stack of oscillators
+    pub fn set_oscillators(&mut self, oscillators: Vec<OscillatorLayer>) {
+        self.oscillators = oscillators;
+    }
+
+    pub fn oscillators(&self) -> &[OscillatorLayer] {
+        &self.oscillators
+    }
+
+    // per voice filter, applied after the effects
+    pub fn set_filter(&mut self, filter: FilterStage) {
+        self.filter = Some(filter);
+    }
+
+    pub fn set_morph(&mut self, morph: Morph) {
+        self.morph = Some(morph);
+    }
+
+    pub fn set_max_voices(&mut self, max_voices: usize) {
+        self.max_voices = max_voices.max(1);
+    }
+
+    pub fn max_voices(&self) -> usize {
+        self.max_voices
+    }
+
+    pub fn set_steal_strategy(&mut self, steal_strategy: StealStrategy) {
+        self.steal_strategy = steal_strategy;
+    }
+
+    pub fn steal_strategy(&self) -> StealStrategy {
+        self.steal_strategy
+    }
+
+    pub fn voices(&self) -> &[Voice] {
+        &self.voices
+    }
+
+    // voices counting towards polyphony, stolen ones still fading out are left out
+    pub fn active_voices(&self) -> usize {
+        self.voices.iter().filter(|voice| voice.is_active()).count()
+    }
+
+    pub fn steal(&mut self, index: usize) {
+        if let Some(voice) = self.voices.get_mut(index) {
+            voice.steal();
+        }
+    }
+
+    fn add_voice(&mut self, midi_note: u8, note: Note) {
+        while self.active_voices() >= self.max_voices {
+            match self.steal_strategy.select(&self.voices) {
+                Some(index) => self.steal(index),
+                None => break,
+            }
+        }
+        self.voices.push(Voice::new(midi_note, note));
+    }
+
+    fn is_monophonic(&self) -> bool {
+        self.mode != InstrumentMode::Polyphonic && !matches!(self.kind, InstrumentKind::Percussive)
+    }
+
+    pub fn controllers(&self) -> Controllers {
+        self.controllers
+    }
+
+    pub fn set_controllers(&mut self, controllers: Controllers) {
+        self.set_channel_volume(controllers.volume);
+        self.set_expression(controllers.expression);
+        self.set_modulation(controllers.modulation);
+        self.set_sustain(controllers.sustain);
+        self.controllers.bend_range = controllers.bend_range;
+        self.controllers.rpn = controllers.rpn;
+        self.controllers.pan = controllers.pan;
+        self.controllers.reverb_send = controllers.reverb_send;
+        self.controllers.chorus_send = controllers.chorus_send;
+        self.set_pitch_bend(controllers.pitch_bend);
+        self.set_channel_pressure(controllers.pressure);
+    }
+
+    pub fn set_velocity_curve(&mut self, velocity_curve: VelocityCurve) {
+        self.velocity_curve = velocity_curve;
+    }
+
+    pub fn velocity_curve(&self) -> VelocityCurve {
+        self.velocity_curve
+    }
+
+    pub fn set_velocity_brightness(&mut self, amount: f32) {
+        self.velocity_brightness = amount;
+    }
+
+    pub fn set_velocity_attack(&mut self, amount: f32) {
+        self.velocity_attack = amount;
+    }
+
+    pub fn set_aftertouch_target(&mut self, aftertouch: AftertouchTarget) {
+        self.aftertouch = aftertouch;
+    }
+
+    pub fn aftertouch_target(&self) -> AftertouchTarget {
+        self.aftertouch
+    }
+
+    pub fn control_change(&mut self, controller: u8, value: u8) {
+        let normalized = value as f32 / 127.;
+        match controller {
+            1 => self.set_modulation(normalized),
+            6 => self.data_entry(value, None),
+            7 => self.set_channel_volume(normalized),
+            10 => self.controllers.pan = ((value as f32 - 64.) / 63.).clamp(-1., 1.),
+            11 => self.set_expression(normalized),
+            38 => self.data_entry(self.controllers.bend_range as u8, Some(value)),
+            64 => self.set_sustain(value >= 64),
+            91 => self.controllers.reverb_send = normalized,
+            93 => self.controllers.chorus_send = normalized,
+            98 | 99 => self.controllers.rpn = RPN_NULL,     // NRPNs aren't supported
+            100 => self.controllers.rpn.1 = value,
+            101 => self.controllers.rpn.0 = value,
+            120 => self.all_sound_off(),
+            121 => self.reset_controllers(),
+            123 => self.all_notes_off(),
+            _ => (),
+        }
+    }
+
+    pub fn set_channel_volume(&mut self, volume: f32) {
+        self.controllers.volume = volume;
+    }
+
+    pub fn set_expression(&mut self, expression: f32) {
+        self.controllers.expression = expression;
+    }
+
+    pub fn set_modulation(&mut self, modulation: f32) {
+        self.controllers.modulation = modulation;
+        self.update_lfo_depth();
+    }
+
+    fn update_lfo_depth(&mut self) {
+        let lfo_amplitude = self.lfo_depth();
+        for voice in &mut self.voices {
+            voice.note_mut().set_lfo_amplitude(lfo_amplitude);
+        }
+    }
+
+    // value from -1 to 1, scaled by the bend range
+    pub fn set_pitch_bend(&mut self, bend: f32) {
+        self.controllers.pitch_bend = bend;
+        let semitones = self.controllers.bend_semitones();
+        for voice in &mut self.voices {
+            voice.note_mut().set_pitch_offset(semitones);
+        }
+    }
+
+    pub fn set_bend_range(&mut self, semitones: f32) {
+        self.controllers.bend_range = semitones;
+        self.set_pitch_bend(self.controllers.pitch_bend);
+    }
+
+    // only RPN 0 (pitch bend sensitivity) is handled: MSB in semitones, LSB in cents
+    fn data_entry(&mut self, msb: u8, lsb: Option<u8>) {
+        if self.controllers.rpn != RPN_BEND_RANGE {
+            return;
+        }
+        let cents = match lsb {
+            Some(cents) => cents as f32,
+            None => (self.controllers.bend_range.fract() * 100.).round(),
+        };
+        self.set_bend_range(msb as f32 + cents / 100.);
+    }
+
+    pub fn set_channel_pressure(&mut self, pressure: f32) {
+        self.controllers.pressure = pressure;
+        match self.aftertouch {
+            AftertouchTarget::None => (),
+            AftertouchTarget::Volume => {
+                for voice in &mut self.voices {
+                    voice.note_mut().set_gain(1. + pressure * AFTERTOUCH_GAIN);
+                }
+            }
+            AftertouchTarget::LfoDepth => self.update_lfo_depth(),
         }
     }
 
-    pub fn note_on(&mut self, midi_note: u8) {
+    pub fn set_key_pressure(&mut self, midi_note: u8, pressure: f32) {
+        let lfo_amplitude = self.lfo_depth() + pressure * MODULATION_DEPTH;
+        for voice in self.voices.iter_mut().filter(|voice| voice.is_held(midi_note)) {
+            let note = voice.note_mut();
+            match self.aftertouch {
+                AftertouchTarget::None => (),
+                AftertouchTarget::Volume => note.set_gain(1. + pressure * AFTERTOUCH_GAIN),
+                AftertouchTarget::LfoDepth => note.set_lfo_amplitude(lfo_amplitude),
+            }
+        }
+    }
+
+    pub fn set_sustain(&mut self, sustain: bool) {
+        self.controllers.sustain = sustain;
+        if !sustain {
+            for midi_note in std::mem::take(&mut self.sustained) {
+                self.release_voices(midi_note);
+            }
+        }
+    }
+
+    // cuts every note immediately, skipping release tails
+    pub fn all_sound_off(&mut self) {
+        self.voices.clear();
+        self.sustained.clear();
+        self.held.clear();
+        self.mono_key = None;
+    }
+
+    pub fn all_notes_off(&mut self) {
+        self.held.clear();
+        let midi_notes: Vec<u8> = self.voices.iter()
+            .filter(|voice| voice.is_active() && !voice.is_released())
+            .map(|voice| voice.key())
+            .collect();
+        for midi_note in midi_notes {
+            self.release(midi_note);
+        }
+    }
+
+    // channel volume is left alone, as recommended by the MIDI spec
+    pub fn reset_controllers(&mut self) {
+        self.set_expression(1.);
+        self.set_modulation(0.);
+        self.set_sustain(false);
+        self.set_pitch_bend(0.);
+        self.set_channel_pressure(0.);
+        self.controllers.rpn = RPN_NULL;
+    }
+
+    fn lfo_depth(&self) -> f32 {
+        let mut modulation = self.controllers.modulation;
+        if self.aftertouch == AftertouchTarget::LfoDepth {
+            modulation += self.controllers.pressure;
+        }
+        self.lfo_amplitude + modulation * MODULATION_DEPTH
+    }
+
+    pub fn note_on(&mut self, midi_note: u8, velocity: u8) {
+        if self.is_monophonic() {
+            self.mono_note_on(midi_note, velocity);
+            return;
+        }
+
+        if let Some(note) = self.build_note(midi_note, velocity) {
+            // a re-struck key lets its previous voice ring out underneath the new one
+            self.release_voices(midi_note);
+            self.sustained.remove(&midi_note);
+            self.add_voice(midi_note, note);
+        }
+    }
+
+    pub fn note_off(&mut self, midi_note: u8) {
+        if self.is_monophonic() {
+            self.mono_note_off(midi_note);
+        }
+        else {
+            self.release(midi_note);
+        }
+    }
+
+    fn release(&mut self, midi_note: u8) {
+        if self.controllers.sustain {
+            if self.voices.iter().any(|voice| voice.is_held(midi_note)) {
+                self.sustained.insert(midi_note);
+            }
+            return;
+        }
+        self.release_voices(midi_note);
+    }
+
+    fn release_voices(&mut self, midi_note: u8) {
+        for voice in self.voices.iter_mut().filter(|voice| voice.is_held(midi_note)) {
+            voice.note_mut().note_off();
+        }
+    }
+
+    // the voice currently playing the monophonic line, if it hasn't been stolen
+    fn mono_voice(&self) -> Option<usize> {
+        let key = self.mono_key?;
+        self.voices.iter().rposition(|voice| voice.key() == key && voice.is_active() && !voice.is_finished())
+    }
+
+    fn mono_note_on(&mut self, midi_note: u8, velocity: u8) {
+        self.held.retain(|&held| held != midi_note);
+        self.held.push(midi_note);
+
+        // with low or high priority the new key may not take over
+        let selected = self.priority.select(&self.held).unwrap_or(midi_note);
+        let sounding = self.mono_voice().is_some_and(|index| !self.voices[index].is_released());
+        if sounding && Some(selected) == self.mono_key {
+            return;
+        }
+        self.mono_velocity = velocity;
+        self.mono_play(selected, velocity);
+    }
+
+    fn mono_note_off(&mut self, midi_note: u8) {
+        self.held.retain(|&held| held != midi_note);
+        if self.mono_key != Some(midi_note) {
+            return;
+        }
+
+        // return to the next note on the stack, or release the voice
+        match self.priority.select(&self.held) {
+            Some(selected) => self.mono_play(selected, self.mono_velocity),
+            None => self.release(midi_note),
+        }
+    }
+
+    // moves the single voice to a new key, reusing it so the envelope continues from its current level
+    fn mono_play(&mut self, midi_note: u8, velocity: u8) {
+        let voice = self.mono_voice();
+        if let Some(key) = self.mono_key.take() {
+            self.sustained.remove(&key);
+        }
+
+        match voice {
+            Some(index) => {
+                let voice = &mut self.voices[index];
+                voice.set_key(midi_note);
+                let note = voice.note_mut();
+                let frequency = midi2freq(midi_note);
+                if self.glide > 0. {
+                    note.glide_to(frequency, self.glide);
+                }
+                else {
+                    note.set_frequency(frequency);
+                }
+
+                // legato only retriggers when the previous note was already released
+                if self.mode == InstrumentMode::Monophonic || !note.is_gated() {
+                    note.set_volume(self.velocity_curve.level(velocity));
+                    note.note_on();
+                }
+            }
+            None => match self.build_note(midi_note, velocity) {
+                Some(note) => self.add_voice(midi_note, note),
+                None => return,
+            }
+        }
+
+        self.mono_key = Some(midi_note);
+    }
+
+    fn build_note(&self, midi_note: u8, velocity: u8) -> Option<Note> {
+        let softness = 1. - velocity as f32 / 127.;
         let mut note = match self.kind {
-            InstrumentKind::Melodic => {
+            InstrumentKind::Melodic | InstrumentKind::Fm(_) => {
                 let frequency = midi2freq(midi_note);
-                Note::from_env(self.waveform, frequency, self.lfo_amplitude, self.lfo, self.amp_envelope, self.freq_envelope, 0., self.effects.clone())
+                let mut amp_envelope = self.amp_envelope;
+                amp_envelope.set_attack(amp_envelope.attack() + self.velocity_attack * softness * VELOCITY_ATTACK_TIME);
+                let mut note = match self.kind {
+                    InstrumentKind::Fm(ref patch) => Note::from_fm(patch, frequency, self.lfo_depth(), self.lfo.clone(), amp_envelope, self.freq_envelope, 0., self.effects.clone()),
+                    _ => Note::from_layers(&self.oscillators, frequency, self.lfo_depth(), self.lfo.clone(), amp_envelope, self.freq_envelope, 0., self.effects.clone()),
+                };
+                if let Some(ref morph) = self.morph {
+                    note.set_morph(morph.clone());
+                }
+                if let Some(mut filter) = self.filter {
+                    filter.set_velocity(velocity as f32 / 127.);
+                    note.set_filter(filter);
+                }
+                note.set_brightness(1. - self.velocity_brightness * softness);
+                note
             }
             InstrumentKind::Percussive => {
                 match midi_note {
                     35 | 36 | 43 => DrumMachine::kick(),
+                    56 => DrumMachine::cowbell(),
                     // 38 | 40 | 45 | 47 => DrumMachine::snare(),
                     // 42 | 44 | 46 | 53 => DrumMachine::hihat(),
                     // 49 | 52 | 57 => DrumMachine::cymbal(),
-                    _ => return
+                    _ => return None
                 }
             }
         };
+        note.set_sample_rate(self.sample_rate);
+        note.set_volume(self.velocity_curve.level(velocity));
+        note.set_pitch_offset(self.controllers.bend_semitones());
+        if self.aftertouch == AftertouchTarget::Volume {
+            note.set_gain(1. + self.controllers.pressure * AFTERTOUCH_GAIN);
+        }
         note.note_on();
-        self.notes.insert(midi_note, note);
+        Some(note)
     }
 
-    pub fn note_off(&mut self, midi_note: u8) {
-        if let Some(note) = self.notes.get_mut(&midi_note) {
-            note.note_off();
-        }
+    // fills `buffer` with the instrument's next samples, voices that finish are dropped at its end
+    pub fn render(&mut self, buffer: &mut [f32]) {
+        buffer.fill(0.);
+        self.block.resize(buffer.len(), 0.);
+        self.voices.retain_mut(|voice| {
+            voice.render(&mut self.block);
+            for (sample, voice_sample) in buffer.iter_mut().zip(&self.block) {
+                *sample += voice_sample;
+            }
+            !voice.is_finished()
+        });
+        let gain = self.volume * self.controllers.gain();
+        buffer.iter_mut().for_each(|sample| *sample *= gain);
     }
 
     pub fn next_sample(&mut self) -> f32 {
         let mut sample = 0.0;
-        self.notes.retain(|_, note| {
-            let note_sample = note.next_sample();
-            sample += note_sample;
-            !matches!(note.state(), EnvelopeState::Idle)
+        self.voices.retain_mut(|voice| {
+            sample += voice.next_sample();
+            !voice.is_finished()
         });
-        sample * self.volume
+        sample * self.volume * self.controllers.gain()
     }
 
     pub fn lead_square(volume: f32) -> Self {
         let kind = InstrumentKind::Melodic;
-        let waveform = Waveform::Square;
+        let waveform = Waveform::BandLimitedSquare;
         let envelope = Envelope::new(0.03, 0.1, 0.7, 0.6, EnvelopeShape::Exponential);
         let lfo = Oscillator::new(Waveform::Sine, 5.);
         let effects = vec![];
@@ -99,7 +655,7 @@ impl Instrument {
 
     pub fn lead_sawtooth(volume: f32) -> Self {
         let kind = InstrumentKind::Melodic;
-        let waveform = Waveform::Sawtooth;
+        let waveform = Waveform::BandLimitedSawtooth;
         let envelope = Envelope::new(0.03, 0.1, 0.7, 0.6, EnvelopeShape::Exponential);
         let lfo = Oscillator::new(Waveform::Sine, 5.);
         let effects = vec![Effect:: Gain(4.), Effect::HardClip(1.)];
@@ -108,21 +664,187 @@ impl Instrument {
 
     pub fn lead_triangle(volume: f32) -> Self {
         let kind = InstrumentKind::Melodic;
-        let waveform = Waveform::Triangle;
+        let waveform = Waveform::BandLimitedTriangle;
         let envelope = Envelope::new(0.03, 0.1, 0.7, 0.6, EnvelopeShape::Exponential);
         let lfo = Oscillator::new(Waveform::Sine, 5.);
         let effects = vec![];
         Self::new(kind, waveform, lfo, 0.005, envelope, None, volume, effects)
     }
 
+    pub fn bass(volume: f32) -> Self {
+        let kind = InstrumentKind::Melodic;
+        let waveform = Waveform::BandLimitedSquare;
+        let envelope = Envelope::new(0.005, 0.2, 0.6, 0.15, EnvelopeShape::Exponential);
+        let lfo = Oscillator::new(Waveform::Sine, 5.);
+        let effects = vec![Effect::Gain(1.5), Effect::SoftExponential(1.)];
+        Self::new(kind, waveform, lfo, 0.002, envelope, None, volume, effects)
+    }
+
+    // resonant 24 dB lowpass swept by its own envelope, brighter for harder notes
+    pub fn acid_bass(volume: f32) -> Self {
+        let kind = InstrumentKind::Melodic;
+        let waveform = Waveform::BandLimitedSawtooth;
+        let envelope = Envelope::new(0.003, 0.3, 0.8, 0.1, EnvelopeShape::Exponential);
+        let lfo = Oscillator::new(Waveform::Sine, 5.);
+        let effects = vec![Effect::Gain(1.5), Effect::SoftExponential(1.)];
+        let mut instrument = Self::new(kind, waveform, lfo, 0., envelope, None, volume, effects);
+
+        let filter = Filter::new(FilterMode::LowPass, FilterSlope::Db24, 250., 0.8);
+        let mut filter = FilterStage::new(filter, Envelope::new(0.002, 0.25, 0., 0.1, EnvelopeShape::Exponential), 4.);
+        filter.set_key_tracking(0.5);
+        filter.set_velocity_amount(1.5);
+        instrument.set_filter(filter);
+        instrument
+    }
+
+    // two detuned saws over a square sub octave
+    pub fn fat_bass(volume: f32) -> Self {
+        let kind = InstrumentKind::Melodic;
+        let waveform = Waveform::BandLimitedSawtooth;
+        let envelope = Envelope::new(0.005, 0.3, 0.7, 0.15, EnvelopeShape::Exponential);
+        let lfo = Oscillator::new(Waveform::Sine, 5.);
+        let effects = vec![Effect::Gain(1.2), Effect::SoftExponential(1.)];
+        let mut instrument = Self::new(kind, waveform.clone(), lfo, 0., envelope, None, volume, effects);
+
+        let mut saws = OscillatorLayer::new(waveform);
+        saws.set_unison(2, 14., 1.);
+        let mut sub = OscillatorLayer::new(Waveform::BandLimitedSquare);
+        sub.set_pitch(-1, 0, 0.);
+        sub.set_level(0.6);
+        instrument.set_oscillators(vec![saws, sub]);
+        instrument
+    }
+
+    // seven detuned saws with random phases
+    pub fn supersaw(volume: f32) -> Self {
+        let kind = InstrumentKind::Melodic;
+        let waveform = Waveform::BandLimitedSawtooth;
+        let envelope = Envelope::new(0.02, 0.3, 0.8, 0.5, EnvelopeShape::Exponential);
+        let lfo = Oscillator::new(Waveform::Sine, 5.);
+        let effects = vec![];
+        let mut instrument = Self::new(kind, waveform.clone(), lfo, 0.002, envelope, None, volume, effects);
+
+        let mut saws = OscillatorLayer::new(waveform);
+        saws.set_unison(7, 40., 1.);
+        let mut sub = OscillatorLayer::new(Waveform::Sine);
+        sub.set_pitch(-1, 0, 0.);
+        sub.set_level(0.4);
+        instrument.set_oscillators(vec![saws, sub]);
+        instrument
+    }
+
+    // a silent oscillator at the note's pitch resets a saw a fifth above it
+    pub fn sync_lead(volume: f32) -> Self {
+        let kind = InstrumentKind::Melodic;
+        let waveform = Waveform::BandLimitedSawtooth;
+        let envelope = Envelope::new(0.01, 0.2, 0.7, 0.3, EnvelopeShape::Exponential);
+        let lfo = Oscillator::new(Waveform::Sine, 5.);
+        let effects = vec![];
+        let mut instrument = Self::new(kind, waveform.clone(), lfo, 0.004, envelope, None, volume, effects);
+
+        let mut master = OscillatorLayer::new(Waveform::Sine);
+        master.set_level(0.);
+        let mut slave = OscillatorLayer::new(waveform);
+        slave.set_pitch(0, 7, 0.);
+        slave.set_modulation(LayerModulation::Sync);
+        instrument.set_oscillators(vec![master, slave]);
+        instrument
+    }
+
+    // dx style electric piano: a bright tine over a mellow body
+    pub fn fm_piano(volume: f32) -> Self {
+        let tine = Envelope::new(0., 0.4, 0., 0.2, EnvelopeShape::Exponential);
+        let body = Envelope::new(0.002, 2.5, 0.2, 0.4, EnvelopeShape::Exponential);
+        let operators = vec![
+            Operator::new(1., 0.7, body),
+            Operator::new(14., 1.2, tine),
+            Operator::new(1., 0.3, body),
+            Operator::new(1., 1.5, Envelope::new(0., 1.5, 0.1, 0.4, EnvelopeShape::Exponential)),
+        ];
+        let kind = InstrumentKind::Fm(FmPatch::new(Algorithm::TwoStacks, operators, 0.3));
+        let envelope = Envelope::new(0., 0., 1., 0.4, EnvelopeShape::Exponential);
+        let lfo = Oscillator::new(Waveform::Sine, 5.);
+        let mut instrument = Self::new(kind, Waveform::Sine, lfo, 0., envelope, None, volume, vec![]);
+        instrument.set_velocity_brightness(0.5);
+        instrument
+    }
+
+    // inharmonic modulator ratio for a struck metal partial series
+    pub fn fm_bell(volume: f32) -> Self {
+        let ring = Envelope::new(0., 4., 0., 2., EnvelopeShape::Exponential);
+        let strike = Envelope::new(0., 1.5, 0., 1., EnvelopeShape::Exponential);
+        let mut shimmer = Operator::new(1., 0.3, ring);
+        shimmer.set_detune(1.5);
+        let operators = vec![
+            Operator::new(1., 0.7, ring),
+            Operator::new(3.5, 3., strike),
+            shimmer,
+            Operator::new(3.5, 2., strike),
+        ];
+        let kind = InstrumentKind::Fm(FmPatch::new(Algorithm::TwoStacks, operators, 0.));
+        let envelope = Envelope::new(0., 0., 1., 2., EnvelopeShape::Exponential);
+        let lfo = Oscillator::new(Waveform::Sine, 5.);
+        Self::new(kind, Waveform::Sine, lfo, 0., envelope, None, volume, vec![])
+    }
+
+    pub fn pad(volume: f32) -> Self {
+        let kind = InstrumentKind::Melodic;
+        let waveform = Waveform::BandLimitedSawtooth;
+        let envelope = Envelope::new(0.5, 0.5, 0.8, 1.2, EnvelopeShape::Linear);
+        let lfo = Oscillator::new(Waveform::Sine, 0.3);
+        let effects = vec![Effect::Gain(0.6)];
+        Self::new(kind, waveform, lfo, 0.004, envelope, None, volume, effects)
+    }
+
+    pub fn organ(volume: f32) -> Self {
+        let kind = InstrumentKind::Melodic;
+        let waveform = Waveform::BandLimitedTriangle;
+        let envelope = Envelope::new(0.01, 0.05, 1., 0.05, EnvelopeShape::Linear);
+        let lfo = Oscillator::new(Waveform::Sine, 6.);
+        let effects = vec![Effect::Gain(3.), Effect::HardClip(1.)];
+        Self::new(kind, waveform, lfo, 0.003, envelope, None, volume, effects)
+    }
+
+    pub fn pluck(volume: f32) -> Self {
+        let kind = InstrumentKind::Melodic;
+        let waveform = Waveform::BandLimitedSawtooth;
+        let envelope = Envelope::new(0.002, 0.8, 0., 0.3, EnvelopeShape::Exponential);
+        let lfo = Oscillator::new(Waveform::Sine, 5.);
+        let effects = vec![Effect::Gain(2.), Effect::HardClip(1.)];
+        Self::new(kind, waveform, lfo, 0., envelope, None, volume, effects)
+    }
+
+    pub fn bell(volume: f32) -> Self {
+        let kind = InstrumentKind::Melodic;
+        let waveform = Waveform::Sine;
+        let envelope = Envelope::new(0.001, 1.5, 0., 0.5, EnvelopeShape::Exponential);
+        let lfo = Oscillator::new(Waveform::Sine, 5.);
+        let effects = vec![Effect::Gain(3.), Effect::Fangs(1.)];
+        Self::new(kind, waveform, lfo, 0., envelope, None, volume, effects)
+    }
+
     pub fn drum_kit(volume: f32) -> Self {
         let kind = InstrumentKind::Percussive;
-        let waveform = Waveform::Triangle;
+        let waveform = Waveform::BandLimitedTriangle;
         let envelope = Envelope::new(0., 0., 0., 0., EnvelopeShape::Exponential);
         let lfo = Oscillator::new(Waveform::Sine, 5.);
         let effects = vec![];
         Self::new(kind, waveform, lfo, 0.005, envelope, None, volume, effects)
     }
+
+    // sweeps from the first frame of the table to the last over the first second of every note
+    pub fn wavetable(volume: f32, table: Arc<Wavetable>) -> Self {
+        let kind = InstrumentKind::Melodic;
+        let waveform = Waveform::Wavetable(table);
+        let envelope = Envelope::new(0.02, 0.3, 0.7, 0.5, EnvelopeShape::Exponential);
+        let lfo = Oscillator::new(Waveform::Sine, 5.);
+        let effects = vec![];
+        let mut instrument = Self::new(kind, waveform, lfo, 0.003, envelope, None, volume, effects);
+        let mut morph = Morph::new(0.);
+        morph.set_envelope(Envelope::new(1., 0.1, 1., 0.5, EnvelopeShape::Linear), 1.);
+        instrument.set_morph(morph);
+        instrument
+    }
 }
 
 
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use crate::synth::instrument::Instrument;
+
+pub const DRUM_CHANNEL: u8 = 9;                 // channel 10 in 1-based numbering
+const MELODIC_VOLUME: f32 = 0.1;
+const DRUM_VOLUME: f32 = 0.25;
+
+type Preset = Box<dyn Fn() -> Instrument + Send>;
+
+// builds the instrument for each General MIDI program number, with optional per-program overrides
+pub struct InstrumentFactory {
+    overrides: HashMap<u8, Preset>,     // key is GM program number
+}
+
+impl Default for InstrumentFactory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InstrumentFactory {
+    pub fn new() -> Self {
+        Self {
+            overrides: HashMap::new(),
+        }
+    }
+
+    pub fn set_program(&mut self, program: u8, preset: impl Fn() -> Instrument + Send + 'static) {
+        self.overrides.insert(program, Box::new(preset));
+    }
+
+    pub fn clear_program(&mut self, program: u8) {
+        self.overrides.remove(&program);
+    }
+
+    pub fn program(&self, program: u8) -> Instrument {
+        match self.overrides.get(&program) {
+            Some(preset) => preset(),
+            None => gm_preset(program)(MELODIC_VOLUME),
+        }
+    }
+
+    pub fn drum_kit(&self) -> Instrument {
+        Instrument::drum_kit(DRUM_VOLUME)
+    }
+
+    pub fn channel_default(&self, channel: u8) -> Instrument {
+        if channel == DRUM_CHANNEL {
+            self.drum_kit()
+        }
+        else {
+            self.program(0)
+        }
+    }
+}
+
+// maps GM programs to the closest duvet preset, mostly one per family of eight
+fn gm_preset(program: u8) -> fn(f32) -> Instrument {
+    match program {
+        4 | 5 => Instrument::fm_piano,              // electric piano
+        0..=7 => Instrument::pluck,                 // piano
+        14 => Instrument::fm_bell,                  // tubular bells
+        8..=15 => Instrument::bell,                 // chromatic percussion
+        16..=23 => Instrument::organ,               // organ
+        24..=28 => Instrument::pluck,               // guitar
+        29..=31 => Instrument::lead_sawtooth,       // overdriven and distortion guitar
+        38 | 39 => Instrument::acid_bass,           // synth bass
+        32..=39 => Instrument::bass,                // bass
+        45 | 46 => Instrument::pluck,               // pizzicato strings, harp
+        40..=47 => Instrument::lead_triangle,       // strings
+        48..=55 => Instrument::pad,                 // ensemble
+        56..=63 => Instrument::lead_sawtooth,       // brass
+        64..=71 => Instrument::lead_square,         // reed
+        72..=79 => Instrument::lead_sine,           // pipe
+        80 => Instrument::lead_square,              // square lead
+        82 => Instrument::lead_sine,                // calliope lead
+        83 => Instrument::lead_triangle,            // chiff lead
+        81..=87 => Instrument::lead_sawtooth,       // synth lead
+        88..=103 => Instrument::pad,                // synth pad and synth effects
+        104..=111 => Instrument::pluck,             // ethnic
+        112..=119 => Instrument::bell,              // percussive
+        _ => Instrument::lead_sine,                 // sound effects
+    }
+}
@@ -1,15 +1,65 @@
-use crate::synth::{effect:: Effect, envelope::{Envelope, EnvelopeState}, oscillator::{Oscillator, Waveform}};
+use std::f32::consts::PI;
+
+use crate::{synth::{effect:: Effect, envelope::{Envelope, EnvelopeState}, filter::FilterStage, fm::{FmPatch, FmVoice}, oscillator::{stack::{OscillatorLayer, OscillatorStack}, Oscillator, Waveform}, wavetable::Morph}, DEFAULT_SAMPLE_RATE};
+
+const PITCH_SMOOTHING_TIME: f32 = 0.005;     // seconds for pitch modulation to settle
+const DARKEST_CUTOFF: f32 = 300.;             // brightness filter cutoff at brightness 0
+const BRIGHTEST_CUTOFF: f32 = 20000.;
+
+// what a note plays, before effects and envelopes
+enum Source {
+    Oscillators(OscillatorStack),
+    Fm(FmVoice),
+}
+
+impl Source {
+    fn set_frequency(&mut self, frequency: f32) {
+        match self {
+            Self::Oscillators(oscillators) => oscillators.set_frequency(frequency),
+            Self::Fm(voice) => voice.set_frequency(frequency),
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        match self {
+            Self::Oscillators(oscillators) => oscillators.set_sample_rate(sample_rate),
+            Self::Fm(voice) => voice.set_sample_rate(sample_rate),
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        match self {
+            Self::Oscillators(oscillators) => oscillators.next_sample(),
+            Self::Fm(voice) => voice.next_sample(),
+        }
+    }
+}
 
 pub struct Note {
-    oscillator: Oscillator,
+    source: Source,
     lfo: Oscillator,
     lfo_amplitude: f32,
     amp_envelope: Envelope,
     freq_envelope: Option<Envelope>,
     effects: Vec<Effect>,
+    filter: Option<FilterStage>,
     frequency: f32,
+    glide_step: f32,            // frequency ratio applied every sample while gliding
+    glide_samples: u32,
+    glide_target: f32,
+    pitch_ratio: f32,           // frequency modulation input, smoothed towards the target
+    pitch_target: f32,
     noise: f32,
     volume: f32,
+    gain: f32,
+    brightness: Option<f32>,    // one-pole lowpass cutoff, None when fully bright
+    brightness_coefficient: f32,
+    lowpass: f32,
+    fade: f32,                  // gain of a note being cut short, 1 while it plays normally
+    fade_step: f32,
+    morph: Option<Morph>,
+    pitches: Vec<f32>,          // the block's pitch at every sample, for the filter's key tracking
+    sample_rate: f32,
 }
 
 impl Note {
@@ -24,34 +74,181 @@ impl Note {
     //     }
     // }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn from_env(waveform: Waveform, frequency: f32, lfo_amplitude: f32, lfo: Oscillator, amp_envelope: Envelope, freq_envelope: Option<Envelope>, noise: f32, effects: Vec<Effect>) -> Self {
+        Self::from_layers(&[OscillatorLayer::new(waveform)], frequency, lfo_amplitude, lfo, amp_envelope, freq_envelope, noise, effects)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_layers(layers: &[OscillatorLayer], frequency: f32, lfo_amplitude: f32, lfo: Oscillator, amp_envelope: Envelope, freq_envelope: Option<Envelope>, noise: f32, effects: Vec<Effect>) -> Self {
+        let source = Source::Oscillators(OscillatorStack::new(layers, frequency));
+        Self::from_source(source, frequency, lfo_amplitude, lfo, amp_envelope, freq_envelope, noise, effects)
+    }
+
+    // the amplitude envelope gates the whole note, the operators shape its timbre
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_fm(patch: &FmPatch, frequency: f32, lfo_amplitude: f32, lfo: Oscillator, amp_envelope: Envelope, freq_envelope: Option<Envelope>, noise: f32, effects: Vec<Effect>) -> Self {
+        let source = Source::Fm(FmVoice::new(patch, frequency));
+        Self::from_source(source, frequency, lfo_amplitude, lfo, amp_envelope, freq_envelope, noise, effects)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn from_source(source: Source, frequency: f32, lfo_amplitude: f32, lfo: Oscillator, amp_envelope: Envelope, freq_envelope: Option<Envelope>, noise: f32, effects: Vec<Effect>) -> Self {
         Self {
-            oscillator: Oscillator::new(waveform, frequency),
+            source,
             lfo,
             lfo_amplitude,
             amp_envelope,
             freq_envelope,
             frequency,
+            glide_step: 1.,
+            glide_samples: 0,
+            glide_target: frequency,
+            pitch_ratio: 1.,
+            pitch_target: 1.,
             effects,
+            filter: None,
             noise,
             volume: 1.,
+            gain: 1.,
+            brightness: None,
+            brightness_coefficient: 1.,
+            lowpass: 0.,
+            fade: 1.,
+            fade_step: 0.,
+            morph: None,
+            pitches: vec![],
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+        }
+    }
+
+    // passed on to everything inside the note, which all start out at the default rate
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f32;
+        self.source.set_sample_rate(sample_rate);
+        self.lfo.set_sample_rate(sample_rate);
+        self.amp_envelope.set_sample_rate(sample_rate);
+        if let Some(ref mut envelope) = self.freq_envelope {
+            envelope.set_sample_rate(sample_rate);
+        }
+        for effect in &mut self.effects {
+            effect.set_sample_rate(sample_rate);
+        }
+        if let Some(ref mut filter) = self.filter {
+            filter.set_sample_rate(sample_rate);
+        }
+        if let Some(ref mut morph) = self.morph {
+            morph.set_sample_rate(sample_rate);
+        }
+        self.update_brightness();
+    }
+
+    pub fn set_filter(&mut self, filter: FilterStage) {
+        self.filter = Some(filter);
+    }
+
+    // from 0 (dull) to 1 (unfiltered), used for velocity to brightness
+    pub fn set_brightness(&mut self, brightness: f32) {
+        if brightness >= 1. {
+            self.brightness = None;
+            return;
+        }
+        self.brightness = Some(DARKEST_CUTOFF * (BRIGHTEST_CUTOFF / DARKEST_CUTOFF).powf(brightness.max(0.)));
+        self.update_brightness();
+    }
+
+    fn update_brightness(&mut self) {
+        if let Some(cutoff) = self.brightness {
+            self.brightness_coefficient = 1. - (-2. * PI * cutoff / self.sample_rate).exp();
         }
     }
 
+    // moves the wavetable position while the note plays
+    pub fn set_morph(&mut self, morph: Morph) {
+        if let Source::Oscillators(ref mut oscillators) = self.source {
+            oscillators.set_position(morph.position());
+        }
+        self.morph = Some(morph);
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+        self.glide_target = frequency;
+        self.glide_samples = 0;
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    // exponential portamento, taking the same time for any interval
+    pub fn glide_to(&mut self, frequency: f32, time: f32) {
+        let samples = (time * self.sample_rate) as u32;
+        if samples == 0 {
+            self.set_frequency(frequency);
+            return;
+        }
+        self.glide_step = (frequency / self.frequency).powf(1. / samples as f32);
+        self.glide_samples = samples;
+        self.glide_target = frequency;
+    }
+
+    // pitch offset in semitones from the note's base frequency, e.g. from pitch bend
+    pub fn set_pitch_offset(&mut self, semitones: f32) {
+        self.pitch_target = 2f32.powf(semitones / 12.);
+    }
+
+    // gain modulation input, e.g. from aftertouch
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
     pub fn set_volume(&mut self, volume: f32) {
         self.volume = volume;
     }
 
+    pub fn set_lfo_amplitude(&mut self, lfo_amplitude: f32) {
+        self.lfo_amplitude = lfo_amplitude;
+    }
+
+    // ramps the note down to silence over `time` seconds, ignoring its release
+    pub fn fade_out(&mut self, time: f32) {
+        self.fade_step = self.fade / (time * self.sample_rate).max(1.);
+    }
+
+    // current output level before effects, used to find the quietest voice
+    pub fn level(&self) -> f32 {
+        self.amp_envelope.level() * self.volume * self.gain * self.fade
+    }
+
     pub fn state(&self) -> EnvelopeState {
         self.amp_envelope.state()
     }
 
+    // still held, i.e. not released or finished
+    pub fn is_gated(&self) -> bool {
+        matches!(self.state(), EnvelopeState::Attack | EnvelopeState::Decay | EnvelopeState::Sustain)
+    }
+
     pub fn note_on(&mut self) {
+        self.pitch_ratio = self.pitch_target;
         self.amp_envelope.trigger();
 
         if let Some(ref mut envelope) = self.freq_envelope {
             envelope.trigger();
         }
+
+        if let Some(ref mut morph) = self.morph {
+            morph.trigger();
+        }
+
+        if let Some(ref mut filter) = self.filter {
+            filter.trigger();
+        }
+
+        if let Source::Fm(ref mut voice) = self.source {
+            voice.note_on();
+        }
     }
 
     pub fn note_off(&mut self) {
@@ -60,32 +257,111 @@ impl Note {
         if let Some(ref mut envelope) = self.freq_envelope {
             envelope.release();
         }
+
+        if let Some(ref mut morph) = self.morph {
+            morph.release();
+        }
+
+        if let Some(ref mut filter) = self.filter {
+            filter.release();
+        }
+
+        if let Source::Fm(ref mut voice) = self.source {
+            voice.note_off();
+        }
     }
 
-    pub fn next_sample(&mut self) -> f32 {
-        let amplitude = self.amp_envelope.get_level();
+    // fills `buffer` with the note's next samples
+    // only the oscillators follow the pitch sample by sample, everything after them runs over the whole block in turn
+    pub fn render(&mut self, buffer: &mut [f32]) {
+        let smoothing = 1. / (PITCH_SMOOTHING_TIME * self.sample_rate);
+        let mut pitches = std::mem::take(&mut self.pitches);
+        pitches.resize(buffer.len(), 0.);
+        let mut finished = None;        // sample a finished fm voice played its last on
 
-        let lfo_value = self.lfo.next_sample();
-        
-        let mut frequency = self.frequency;
+        for (index, (sample, pitch)) in buffer.iter_mut().zip(&mut pitches).enumerate() {
+            *pitch = self.next_pitch(smoothing);
+            let mut frequency = *pitch;
+            if let Some(ref mut envelope) = self.freq_envelope {
+                frequency *= envelope.get_level();
+            }
+            frequency *= 1.0 + self.lfo.next_sample() * self.lfo_amplitude;
+            self.source.set_frequency(frequency);
 
-        if let Some(ref mut envelope) = self.freq_envelope {
-            frequency *= envelope.get_level();
+            if let (Some(morph), Source::Oscillators(oscillators)) = (&mut self.morph, &mut self.source) {
+                oscillators.set_position(morph.next_position());
+            }
+
+            let noise = 2. * rand::random::<f32>() - 1.;
+            *sample = (1.0 - self.noise) * self.source.next_sample() + self.noise * noise;
+
+            if let Source::Fm(ref voice) = self.source {
+                if finished.is_none() && voice.is_finished() {
+                    finished = Some(index);
+                }
+            }
         }
-        
-        frequency *= 1.0 + lfo_value * self.lfo_amplitude;
-        self.oscillator.set_frequency(frequency);
-        
-        let noise = 2. * rand::random::<f32>() - 1.;
-        let mut sample = (1.0 - self.noise) * self.oscillator.next_sample() + self.noise * noise;
 
         // apply effects
-        for effect in self.effects.clone() {
-            sample = effect.apply(sample);
+        for effect in &mut self.effects {
+            buffer.iter_mut().for_each(|sample| *sample = effect.apply(*sample));
+        }
+
+        if let Some(ref mut filter) = self.filter {
+            for (sample, &pitch) in buffer.iter_mut().zip(&pitches) {
+                *sample = filter.process(*sample, pitch);
+            }
         }
+        self.pitches = pitches;
+
+        if self.brightness.is_some() {
+            for sample in buffer.iter_mut() {
+                self.lowpass += (*sample - self.lowpass) * self.brightness_coefficient;
+                *sample = self.lowpass;
+            }
+        }
+
+        let gain = self.volume * self.gain;
+        for (index, sample) in buffer.iter_mut().enumerate() {
+            let amplitude = self.amp_envelope.get_level();
 
-        sample *= amplitude;
+            // a finished fm note ends even when its gate envelope still sustains
+            if finished.is_some_and(|finished| index >= finished) {
+                self.amp_envelope.stop();
+            }
+
+            if self.fade_step > 0. {
+                self.fade -= self.fade_step;
+                if self.fade <= 0. {
+                    self.fade = 0.;
+                    self.amp_envelope.stop();
+                }
+            }
+
+            *sample *= amplitude * gain * self.fade;
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let mut sample = [0.];
+        self.render(&mut sample);
+        sample[0]
+    }
+
+    // glides and smooths the pitch by a sample
+    fn next_pitch(&mut self, smoothing: f32) -> f32 {
+        if self.glide_samples > 0 {
+            self.glide_samples -= 1;
+            self.frequency = if self.glide_samples == 0 { self.glide_target } else { self.frequency * self.glide_step };
+        }
+
+        if self.pitch_ratio != self.pitch_target {
+            self.pitch_ratio += (self.pitch_target - self.pitch_ratio) * smoothing;
+            if (self.pitch_target - self.pitch_ratio).abs() < 1e-6 {
+                self.pitch_ratio = self.pitch_target;
+            }
+        }
 
-        sample * self.volume
+        self.frequency * self.pitch_ratio
     }
 }
\ No newline at end of file
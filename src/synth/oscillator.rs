@@ -1,8 +1,10 @@
-use std::f32::consts::PI;
+pub mod stack;
 
-use crate::SAMPLE_RATE;
+use std::{f32::consts::PI, sync::Arc};
 
-#[derive(Clone, Copy, Debug)]
+use crate::{synth::wavetable::Wavetable, DEFAULT_SAMPLE_RATE};
+
+#[derive(Clone, Debug)]
 pub enum Waveform {
     Sine,
     Square,
@@ -10,15 +12,25 @@ pub enum Waveform {
     Sawtooth,
     AnalogSawtooth,
     Exp,
+    // polyblep corrected versions of the naive shapes, free of audible aliasing
+    BandLimitedSquare,
+    BandLimitedTriangle,
+    BandLimitedSawtooth,
+    Wavetable(Arc<Wavetable>),
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct Oscillator {
     waveform: Waveform,
     frequency: f32,
     phase: f32,
     duty: f32,
     harmonics: u32,
+    position: f32,
+    wrap: Option<f32>,      // when the last sample completed a cycle, in samples after it
+    sync: Option<(f32, f32)>,   // a pending hard sync, phase to restart from and samples after the next one
+    sync_correction: f32,   // what's left of the last sync's blep, for the sample after it
+    sample_rate: f32,
 }
 
 impl Oscillator {
@@ -29,6 +41,11 @@ impl Oscillator {
             phase: 0.0,
             duty: 0.5,              // duty cycle; only used for square waves
             harmonics: 50,          // number of harmonics summed; only used for sawtooth waves
+            position: 0.,           // from first to last frame; only used for wavetables
+            wrap: None,
+            sync: None,
+            sync_correction: 0.,
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
         }
     }
 
@@ -40,6 +57,10 @@ impl Oscillator {
         self.frequency
     }
 
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f32;
+    }
+
     pub fn set_duty(&mut self, duty: f32) {
         self.duty = duty;
     }
@@ -56,22 +77,217 @@ impl Oscillator {
         self.harmonics
     }
 
+    // from 0 to 1, where the next cycle starts, e.g. on hard sync
+    pub fn set_phase(&mut self, phase: f32) {
+        self.phase = phase.rem_euclid(1.);
+    }
+
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+    pub fn wrapped(&self) -> bool {
+        self.wrap.is_some()
+    }
+
+    // from 0 to 1, how far past the last sample the cycle completed, None if it didn't
+    pub fn wrap_delay(&self) -> Option<f32> {
+        self.wrap
+    }
+
+    // hard sync, restarts the cycle at `phase` `delay` samples (0 to 1) after the next one is read
+    // band limited waveforms get the jump this makes smoothed like their own
+    pub fn sync(&mut self, phase: f32, delay: f32) {
+        self.sync = Some((phase.rem_euclid(1.), delay.clamp(0., 1.)));
+    }
+
+    pub fn set_position(&mut self, position: f32) {
+        self.position = position;
+    }
+
+    pub fn position(&self) -> f32 {
+        self.position
+    }
+
     pub fn next_sample(&mut self) -> f32 {
-        let sample = match self.waveform {
-            Waveform::Sine => (2.0 * PI * self.phase).sin(),
-            Waveform::Square => if self.phase < self.duty { 1.0 } else { -1.0 },
-            Waveform::Triangle => if self.phase < 0.5 { 4.0 * self.phase - 1.0 } else { 3.0 - 4.0 * self.phase },
-            Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+        self.modulated_sample(0.)
+    }
+
+    // reads the wave `offset` cycles away from the running phase, for phase modulation
+    pub fn modulated_sample(&mut self, offset: f32) -> f32 {
+        let phase = if offset == 0. { self.phase } else { (self.phase + offset).rem_euclid(1.) };
+        let increment = (self.frequency / self.sample_rate).abs().min(0.5);
+        let sample = match &self.waveform {
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Square => if phase < self.duty { 1.0 } else { -1.0 },
+            Waveform::Triangle => if phase < 0.5 { 4.0 * phase - 1.0 } else { 3.0 - 4.0 * phase },
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
             Waveform::AnalogSawtooth => {
+                // harmonics above nyquist would only alias
+                let limit = (0.5 / increment.max(f32::EPSILON)).ceil() as u32;
                 let mut sample = 0.0;
-                for k in 1..self.harmonics {
-                    sample += (2.0 * PI * k as f32 * self.phase).sin() / k as f32;
+                for k in 1..self.harmonics.min(limit) {
+                    sample += (2.0 * PI * k as f32 * phase).sin() / k as f32;
                 }
                 -2.0/PI * sample
             }
-            Waveform::Exp => (2. * self.phase - 1.).powf(3.) + 0.5
+            Waveform::Exp => (2. * phase - 1.).powf(3.) + 0.5,
+            Waveform::BandLimitedSquare => {
+                let naive = if phase < self.duty { 1.0 } else { -1.0 };
+                naive + poly_blep(phase, increment) - poly_blep((phase - self.duty).rem_euclid(1.), increment)
+            }
+            Waveform::BandLimitedTriangle => {
+                // the slope flips by 8 per cycle at both corners
+                let naive = if phase < 0.5 { 4.0 * phase - 1.0 } else { 3.0 - 4.0 * phase };
+                let corners = poly_blamp(phase, increment) - poly_blamp((phase + 0.5) % 1., increment);
+                naive + 8. * increment * corners
+            }
+            Waveform::BandLimitedSawtooth => 2.0 * phase - 1.0 - poly_blep(phase, increment),
+            Waveform::Wavetable(table) => table.sample(self.position, phase, increment),
         };
-        self.phase = (self.phase + self.frequency / SAMPLE_RATE as f32) % 1.0;
+        let sample = sample + std::mem::take(&mut self.sync_correction);
+
+        let advance = self.frequency / self.sample_rate;
+        if let Some((reset, delay)) = self.sync.take() {
+            return sample + self.restart(reset, delay, advance, increment);
+        }
+        let phase = self.phase + advance;
+        self.wrap = (phase >= 1.0).then(|| (1. - self.phase) / advance);
+        self.phase = phase % 1.0;
         sample
     }
-}
\ No newline at end of file
+
+    // moves to the synced phase, returns the blep for the sample just read and keeps the rest for the next one
+    fn restart(&mut self, reset: f32, delay: f32, advance: f32, increment: f32) -> f32 {
+        let phase = self.phase;
+        let old = phase + delay * advance;
+        let new = (reset + (1. - delay) * advance).rem_euclid(1.);
+        self.wrap = (old >= 1.).then(|| (1. - phase) / advance);
+        self.phase = new;
+
+        let (Some(from), Some(to), Some(start), Some(end)) = (self.naive(old % 1.), self.naive(reset), self.naive(0.), self.naive(1.)) else {
+            return 0.;
+        };
+        let step = to - from;
+        let wrap_step = start - end;
+        let mut before = step / 2. * poly_blep(1. - delay * increment, increment);
+        self.sync_correction = step / 2. * poly_blep((1. - delay) * increment, increment);
+
+        // the waveform's own blep is taken back out wherever it was for a wrap the sync replaces,
+        // one about to happen on the sample just read, or one set off by restarting close to phase 0
+        if old < 1. && phase > 1. - increment {
+            before -= wrap_step / 2. * poly_blep(phase, increment);
+        }
+        if new < increment {
+            self.sync_correction -= wrap_step / 2. * poly_blep(new, increment);
+        }
+        before
+    }
+
+    // the uncorrected shape of the band limited waveforms, which are the only ones to get a blep on sync
+    fn naive(&self, phase: f32) -> Option<f32> {
+        match self.waveform {
+            Waveform::BandLimitedSquare => Some(if phase < self.duty { 1.0 } else { -1.0 }),
+            Waveform::BandLimitedTriangle => Some(if phase < 0.5 { 4.0 * phase - 1.0 } else { 3.0 - 4.0 * phase }),
+            Waveform::BandLimitedSawtooth => Some(2.0 * phase - 1.0),
+            _ => None,
+        }
+    }
+}
+
+// residual of a band limited step of height 2 at phase 0, spread over the samples next to it
+fn poly_blep(phase: f32, increment: f32) -> f32 {
+    if phase < increment {
+        let t = phase / increment;
+        -(1. - t) * (1. - t)
+    }
+    else if phase > 1. - increment {
+        let t = (phase - 1.) / increment;
+        (t + 1.) * (t + 1.)
+    }
+    else {
+        0.
+    }
+}
+
+// integral of the blep residual, corrects a unit change of slope per sample at phase 0
+fn poly_blamp(phase: f32, increment: f32) -> f32 {
+    if phase < increment {
+        let t = 1. - phase / increment;
+        t * t * t / 6.
+    }
+    else if phase > 1. - increment {
+        let t = (phase - 1.) / increment + 1.;
+        t * t * t / 6.
+    }
+    else {
+        0.
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    pub(crate) const RATE: usize = 48000;
+    const FREQUENCY: usize = 4111;      // doesn't divide the rate, so aliases miss the harmonics
+    // two sample polyblep still leaves some aliasing this high up, most of it close to nyquist
+    const MAX_ALIASING: f64 = -20.;
+    const MIN_IMPROVEMENT: f64 = 12.;       // over the naive waveform
+
+    fn oscillator_aliasing(waveform: Waveform) -> f64 {
+        let mut oscillator = Oscillator::new(waveform, FREQUENCY as f32);
+        oscillator.set_sample_rate(RATE as u32);
+        aliasing(FREQUENCY, || oscillator.next_sample())
+    }
+
+    // share of a second of a signal's energy outside the harmonics of `frequency` Hz, in dB
+    // one second at whole Hz puts every partial and every alias on its own DFT bin,
+    // so the harmonics are measured directly and the rest is what Parseval leaves
+    pub(crate) fn aliasing(frequency: usize, mut next_sample: impl FnMut() -> f32) -> f64 {
+        let samples: Vec<f64> = (0..RATE).map(|_| next_sample() as f64).collect();
+
+        let total: f64 = samples.iter().map(|sample| sample * sample).sum();
+        let harmonic: f64 = (0..=RATE / 2 / frequency)
+            .map(|k| {
+                let bin = k * frequency;
+                let (mut re, mut im) = (0., 0.);
+                for (n, sample) in samples.iter().enumerate() {
+                    let angle = 2. * PI * ((bin * n) % RATE) as f64 / RATE as f64;
+                    re += sample * angle.cos();
+                    im -= sample * angle.sin();
+                }
+                let energy = (re * re + im * im) / RATE as f64;
+                // bins other than dc and nyquist have a mirror image holding the same energy
+                if bin == 0 { energy } else { 2. * energy }
+            })
+            .sum();
+        10. * ((total - harmonic).max(f64::MIN_POSITIVE) / total).log10()
+    }
+
+    #[test]
+    fn band_limited_sawtooth_aliasing() {
+        let naive = oscillator_aliasing(Waveform::Sawtooth);
+        let band_limited = oscillator_aliasing(Waveform::BandLimitedSawtooth);
+        assert!(band_limited < MAX_ALIASING, "band limited sawtooth aliasing at {band_limited:.1} dB");
+        assert!(band_limited < naive - MIN_IMPROVEMENT, "naive at {naive:.1} dB, band limited at {band_limited:.1} dB");
+    }
+
+    #[test]
+    fn band_limited_square_aliasing() {
+        let naive = oscillator_aliasing(Waveform::Square);
+        let band_limited = oscillator_aliasing(Waveform::BandLimitedSquare);
+        assert!(band_limited < MAX_ALIASING, "band limited square aliasing at {band_limited:.1} dB");
+        assert!(band_limited < naive - MIN_IMPROVEMENT, "naive at {naive:.1} dB, band limited at {band_limited:.1} dB");
+    }
+
+    #[test]
+    fn band_limited_triangle_aliasing() {
+        let naive = oscillator_aliasing(Waveform::Triangle);
+        let band_limited = oscillator_aliasing(Waveform::BandLimitedTriangle);
+        assert!(band_limited < MAX_ALIASING, "band limited triangle aliasing at {band_limited:.1} dB");
+        assert!(band_limited < naive - MIN_IMPROVEMENT, "naive at {naive:.1} dB, band limited at {band_limited:.1} dB");
+    }
+}
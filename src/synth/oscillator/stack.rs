@@ -0,0 +1,216 @@
+use crate::synth::oscillator::{Oscillator, Waveform};
+
+// how a layer is driven by the layer right before it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayerModulation {
+    None,
+    Sync,       // restarts its cycle whenever the previous layer completes one
+    Ring,       // multiplied by the previous layer's output
+}
+
+// one oscillator of an instrument's patch, possibly doubled into detuned unison voices
+#[derive(Clone, Debug)]
+pub struct OscillatorLayer {
+    waveform: Waveform,
+    octave: i32,
+    semitone: i32,
+    cents: f32,
+    level: f32,
+    phase: f32,             // phase every note starts from
+    unison: usize,
+    detune: f32,            // cents between the lowest and highest unison voice
+    spread: f32,            // 0 starts every unison voice at `phase`, 1 at random phases
+    modulation: LayerModulation,
+}
+
+impl OscillatorLayer {
+    pub fn new(waveform: Waveform) -> Self {
+        Self {
+            waveform,
+            octave: 0,
+            semitone: 0,
+            cents: 0.,
+            level: 1.,
+            phase: 0.,
+            unison: 1,
+            detune: 0.,
+            spread: 0.,
+            modulation: LayerModulation::None,
+        }
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    pub fn waveform(&self) -> &Waveform {
+        &self.waveform
+    }
+
+    pub fn set_pitch(&mut self, octave: i32, semitone: i32, cents: f32) {
+        self.octave = octave;
+        self.semitone = semitone;
+        self.cents = cents;
+    }
+
+    // frequency ratio to the note's pitch
+    pub fn ratio(&self) -> f32 {
+        let semitones = 12. * self.octave as f32 + self.semitone as f32 + self.cents / 100.;
+        2f32.powf(semitones / 12.)
+    }
+
+    pub fn set_level(&mut self, level: f32) {
+        self.level = level;
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn set_phase(&mut self, phase: f32) {
+        self.phase = phase;
+    }
+
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+    pub fn set_unison(&mut self, voices: usize, detune: f32, spread: f32) {
+        self.unison = voices.max(1);
+        self.detune = detune;
+        self.spread = spread.clamp(0., 1.);
+    }
+
+    pub fn unison(&self) -> usize {
+        self.unison
+    }
+
+    pub fn set_modulation(&mut self, modulation: LayerModulation) {
+        self.modulation = modulation;
+    }
+
+    pub fn modulation(&self) -> LayerModulation {
+        self.modulation
+    }
+}
+
+// the running oscillators of a layer, one per unison voice
+struct LayerState {
+    oscillators: Vec<Oscillator>,
+    ratios: Vec<f32>,       // layer pitch combined with each voice's detune
+    phase: f32,
+    gain: f32,
+    modulation: LayerModulation,
+}
+
+// every oscillator a single note plays, built from its instrument's layers
+pub struct OscillatorStack {
+    layers: Vec<LayerState>,
+}
+
+impl OscillatorStack {
+    pub fn new(layers: &[OscillatorLayer], frequency: f32) -> Self {
+        let layers = layers.iter()
+            .map(|layer| {
+                let ratio = layer.ratio();
+                let ratios: Vec<f32> = (0..layer.unison)
+                    .map(|voice| {
+                        let offset = match layer.unison {
+                            1 => 0.,
+                            voices => layer.detune * (voice as f32 / (voices - 1) as f32 - 0.5),
+                        };
+                        ratio * 2f32.powf(offset / 1200.)
+                    })
+                    .collect();
+                let oscillators = ratios.iter()
+                    .map(|ratio| {
+                        let mut oscillator = Oscillator::new(layer.waveform.clone(), frequency * ratio);
+                        oscillator.set_phase(layer.phase + layer.spread * rand::random::<f32>());
+                        oscillator
+                    })
+                    .collect();
+                LayerState {
+                    oscillators,
+                    ratios,
+                    phase: layer.phase,
+                    gain: layer.level / (layer.unison as f32).sqrt(),      // unison adds up as uncorrelated voices
+                    modulation: layer.modulation,
+                }
+            })
+            .collect();
+        Self { layers }
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        for layer in &mut self.layers {
+            for (oscillator, ratio) in layer.oscillators.iter_mut().zip(&layer.ratios) {
+                oscillator.set_frequency(frequency * ratio);
+            }
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        for oscillator in self.layers.iter_mut().flat_map(|layer| layer.oscillators.iter_mut()) {
+            oscillator.set_sample_rate(sample_rate);
+        }
+    }
+
+    pub fn set_position(&mut self, position: f32) {
+        for oscillator in self.layers.iter_mut().flat_map(|layer| layer.oscillators.iter_mut()) {
+            oscillator.set_position(position);
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let mut sample = 0.;
+        let mut previous = 0.;          // last layer's output before its level
+        let mut previous_wrap = None;    // when the last layer's first oscillator completed a cycle
+        for layer in &mut self.layers {
+            if let (LayerModulation::Sync, Some(delay)) = (layer.modulation, previous_wrap) {
+                for oscillator in &mut layer.oscillators {
+                    oscillator.sync(layer.phase, delay);
+                }
+            }
+
+            let mut output: f32 = layer.oscillators.iter_mut().map(|oscillator| oscillator.next_sample()).sum();
+            if layer.modulation == LayerModulation::Ring {
+                output *= previous;
+            }
+
+            previous = output / layer.oscillators.len() as f32;
+            previous_wrap = layer.oscillators.first().and_then(|oscillator| oscillator.wrap_delay());
+            sample += output * layer.gain;
+        }
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::oscillator::tests::{aliasing, RATE};
+
+    const FREQUENCY: usize = 211;
+    const MIN_IMPROVEMENT: f64 = 6.;        // over the same sync without the blep on the reset
+
+    // a silent master restarting a slave at 0.6 times its pitch, so the slave never wraps by itself
+    // and every step in the naive sawtooth is one the reset blep has to smooth
+    fn sync_aliasing(waveform: Waveform) -> f64 {
+        let mut master = OscillatorLayer::new(Waveform::Sine);
+        master.set_level(0.);
+        let mut slave = OscillatorLayer::new(waveform);
+        slave.set_pitch(0, -9, 15.64);
+        slave.set_modulation(LayerModulation::Sync);
+
+        let mut stack = OscillatorStack::new(&[master, slave], FREQUENCY as f32);
+        stack.set_sample_rate(RATE as u32);
+        aliasing(FREQUENCY, || stack.next_sample())
+    }
+
+    #[test]
+    fn band_limited_hard_sync_aliasing() {
+        let uncorrected = sync_aliasing(Waveform::Sawtooth);
+        let band_limited = sync_aliasing(Waveform::BandLimitedSawtooth);
+        assert!(band_limited < uncorrected - MIN_IMPROVEMENT, "uncorrected at {uncorrected:.1} dB, band limited at {band_limited:.1} dB");
+    }
+}
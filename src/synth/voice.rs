@@ -0,0 +1,113 @@
+use crate::synth::{envelope::EnvelopeState, note::Note};
+
+const STEAL_FADE_TIME: f32 = 0.005;     // seconds for a stolen voice to fade out
+
+// which voice gives way when the polyphony limit is reached
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StealStrategy {
+    Oldest,
+    Quietest,
+    ReleasedFirst,      // oldest released voice, or the oldest one if every voice is held
+}
+
+impl StealStrategy {
+    // whether `a` should be stolen before `b`
+    pub fn prefers(self, a: &Voice, b: &Voice) -> bool {
+        match self {
+            Self::Oldest => a.age > b.age,
+            Self::Quietest => a.note.level() < b.note.level(),
+            Self::ReleasedFirst => match (a.is_released(), b.is_released()) {
+                (true, false) => true,
+                (false, true) => false,
+                _ => a.age > b.age,
+            }
+        }
+    }
+
+    // index of the voice to steal, stolen voices are already on their way out
+    pub fn select(self, voices: &[Voice]) -> Option<usize> {
+        let mut selected: Option<usize> = None;
+        for (i, voice) in voices.iter().enumerate() {
+            if voice.stolen {
+                continue;
+            }
+            if selected.is_none_or(|j| self.prefers(voice, &voices[j])) {
+                selected = Some(i);
+            }
+        }
+        selected
+    }
+}
+
+// a sounding note and the key that started it
+pub struct Voice {
+    key: u8,
+    note: Note,
+    age: u32,           // samples since the voice started
+    stolen: bool,
+}
+
+impl Voice {
+    pub fn new(key: u8, note: Note) -> Self {
+        Self {
+            key,
+            note,
+            age: 0,
+            stolen: false,
+        }
+    }
+
+    pub fn key(&self) -> u8 {
+        self.key
+    }
+
+    pub fn set_key(&mut self, key: u8) {
+        self.key = key;
+    }
+
+    pub fn note(&self) -> &Note {
+        &self.note
+    }
+
+    pub fn note_mut(&mut self) -> &mut Note {
+        &mut self.note
+    }
+
+    pub fn age(&self) -> u32 {
+        self.age
+    }
+
+    // counts towards polyphony and responds to the key that started it
+    pub fn is_active(&self) -> bool {
+        !self.stolen
+    }
+
+    pub fn is_released(&self) -> bool {
+        !self.note.is_gated()
+    }
+
+    // held by its key, i.e. not released, stolen or finished
+    pub fn is_held(&self, key: u8) -> bool {
+        self.key == key && !self.stolen && self.note.is_gated()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.note.state(), EnvelopeState::Idle)
+    }
+
+    // cut short with a quick fade instead of an abrupt stop
+    pub fn steal(&mut self) {
+        self.stolen = true;
+        self.note.fade_out(STEAL_FADE_TIME);
+    }
+
+    pub fn render(&mut self, buffer: &mut [f32]) {
+        self.age = self.age.saturating_add(buffer.len() as u32);
+        self.note.render(buffer);
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        self.age = self.age.saturating_add(1);
+        self.note.next_sample()
+    }
+}
@@ -0,0 +1,210 @@
+use std::{f32::consts::PI, fmt, io, path::Path};
+
+use hound::{SampleFormat, WavReader};
+
+use crate::{error::Result, synth::{envelope::Envelope, oscillator::Oscillator}};
+
+const TABLE_SIZE: usize = 2048;
+const MAX_HARMONICS: usize = TABLE_SIZE / 2;
+const LEVELS: usize = 11;               // MAX_HARMONICS halved down to a single harmonic
+
+// one cycle stored at decreasing bandwidths, one table per octave
+struct Frame {
+    levels: Vec<Vec<f32>>,
+}
+
+// a sequence of single-cycle waves, read at a position that morphs between neighbouring frames
+pub struct Wavetable {
+    frames: Vec<Frame>,
+}
+
+impl fmt::Debug for Wavetable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Wavetable").field("frames", &self.frames.len()).finish()
+    }
+}
+
+impl Wavetable {
+    // each frame lists sine amplitudes for harmonics 1, 2, 3...
+    pub fn from_harmonics(frames: &[Vec<f32>]) -> Self {
+        let spectra = frames.iter()
+            .map(|amplitudes| amplitudes.iter().take(MAX_HARMONICS).map(|&amplitude| (0., amplitude)).collect())
+            .collect();
+        Self::from_spectra(spectra)
+    }
+
+    // the file is cut into `frames` equal single-cycle waves, only its first channel is used
+    pub fn from_wav(path: impl AsRef<Path>, frames: usize) -> Result<Self> {
+        let mut reader = WavReader::open(path)?;
+        let spec = reader.spec();
+        let samples: Vec<f32> = match spec.sample_format {
+            SampleFormat::Float => reader.samples::<f32>().collect::<std::result::Result<_, _>>()?,
+            SampleFormat::Int => {
+                let scale = 1. / (1u32 << (spec.bits_per_sample - 1)) as f32;
+                reader.samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 * scale))
+                    .collect::<std::result::Result<_, _>>()?
+            }
+        };
+        let samples: Vec<f32> = samples.into_iter().step_by(spec.channels as usize).collect();
+
+        if frames == 0 || samples.len() < frames * 2 {
+            let message = format!("can't cut {} samples into {} wavetable frames", samples.len(), frames);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+        }
+        let frame_len = samples.len() / frames;
+        let spectra = samples.chunks_exact(frame_len).map(analyze).collect();
+        Ok(Self::from_spectra(spectra))
+    }
+
+    // cosine and sine amplitude of every harmonic, per frame
+    fn from_spectra(spectra: Vec<Vec<(f32, f32)>>) -> Self {
+        let mut frames: Vec<Frame> = spectra.iter()
+            .map(|spectrum| Frame {
+                levels: (0..LEVELS).map(|level| synthesize(spectrum, MAX_HARMONICS >> level)).collect(),
+            })
+            .collect();
+        if frames.is_empty() {
+            frames.push(Frame { levels: vec![vec![0.; TABLE_SIZE]; LEVELS] });
+        }
+
+        // a single gain for every frame keeps their relative loudness
+        let peak = frames.iter()
+            .flat_map(|frame| frame.levels[0].iter())
+            .fold(0f32, |peak, sample| peak.max(sample.abs()));
+        if peak > 0. {
+            for table in frames.iter_mut().flat_map(|frame| frame.levels.iter_mut()) {
+                table.iter_mut().for_each(|sample| *sample /= peak);
+            }
+        }
+        Self { frames }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    // position from 0 (first frame) to 1 (last frame), phase increment picks the table without aliasing
+    pub fn sample(&self, position: f32, phase: f32, increment: f32) -> f32 {
+        let level = ((TABLE_SIZE as f32 * increment).log2().ceil().max(0.) as usize).min(LEVELS - 1);
+
+        let position = position.clamp(0., 1.) * (self.frames.len() - 1) as f32;
+        let index = position as usize;
+        let current = lookup(&self.frames[index].levels[level], phase);
+        match self.frames.get(index + 1) {
+            Some(next) => {
+                let fraction = position - index as f32;
+                current + (lookup(&next.levels[level], phase) - current) * fraction
+            }
+            None => current,
+        }
+    }
+}
+
+// moves a voice's wavetable position over time, from an envelope and an LFO
+#[derive(Clone, Debug)]
+pub struct Morph {
+    position: f32,
+    envelope: Option<Envelope>,
+    envelope_depth: f32,
+    lfo: Option<Oscillator>,
+    lfo_depth: f32,
+}
+
+impl Morph {
+    pub fn new(position: f32) -> Self {
+        Self {
+            position,
+            envelope: None,
+            envelope_depth: 0.,
+            lfo: None,
+            lfo_depth: 0.,
+        }
+    }
+
+    // the envelope moves the position by up to `depth`, negative depths sweep backwards
+    pub fn set_envelope(&mut self, envelope: Envelope, depth: f32) {
+        self.envelope = Some(envelope);
+        self.envelope_depth = depth;
+    }
+
+    pub fn set_lfo(&mut self, lfo: Oscillator, depth: f32) {
+        self.lfo = Some(lfo);
+        self.lfo_depth = depth;
+    }
+
+    pub fn position(&self) -> f32 {
+        self.position
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        if let Some(ref mut envelope) = self.envelope {
+            envelope.set_sample_rate(sample_rate);
+        }
+        if let Some(ref mut lfo) = self.lfo {
+            lfo.set_sample_rate(sample_rate);
+        }
+    }
+
+    pub fn trigger(&mut self) {
+        if let Some(ref mut envelope) = self.envelope {
+            envelope.trigger();
+        }
+    }
+
+    pub fn release(&mut self) {
+        if let Some(ref mut envelope) = self.envelope {
+            envelope.release();
+        }
+    }
+
+    pub fn next_position(&mut self) -> f32 {
+        let mut position = self.position;
+        if let Some(ref mut envelope) = self.envelope {
+            position += envelope.get_level() * self.envelope_depth;
+        }
+        if let Some(ref mut lfo) = self.lfo {
+            position += lfo.next_sample() * self.lfo_depth;
+        }
+        position.clamp(0., 1.)
+    }
+}
+
+// harmonic amplitudes of one cycle of any length, DC is dropped
+fn analyze(cycle: &[f32]) -> Vec<(f32, f32)> {
+    let len = cycle.len();
+    (1..=(len / 2).min(MAX_HARMONICS))
+        .map(|harmonic| {
+            let (mut cos, mut sin) = (0., 0.);
+            for (i, sample) in cycle.iter().enumerate() {
+                let angle = 2. * PI * ((harmonic * i) % len) as f32 / len as f32;
+                cos += sample * angle.cos();
+                sin += sample * angle.sin();
+            }
+            (2. * cos / len as f32, 2. * sin / len as f32)
+        })
+        .collect()
+}
+
+fn synthesize(spectrum: &[(f32, f32)], harmonics: usize) -> Vec<f32> {
+    let mut table = vec![0.; TABLE_SIZE];
+    for (harmonic, &(cos, sin)) in spectrum.iter().take(harmonics).enumerate() {
+        if cos == 0. && sin == 0. {
+            continue;
+        }
+        let harmonic = harmonic + 1;
+        for (i, sample) in table.iter_mut().enumerate() {
+            let angle = 2. * PI * ((harmonic * i) % TABLE_SIZE) as f32 / TABLE_SIZE as f32;
+            *sample += cos * angle.cos() + sin * angle.sin();
+        }
+    }
+    table
+}
+
+fn lookup(table: &[f32], phase: f32) -> f32 {
+    let position = phase * TABLE_SIZE as f32;
+    let index = position as usize % TABLE_SIZE;
+    let fraction = position - position.floor();
+    let current = table[index];
+    current + (table[(index + 1) % TABLE_SIZE] - current) * fraction
+}
@@ -0,0 +1,44 @@
+// needs the ALSA sequencer (snd-seq), run with `cargo test -- --ignored`
+
+use std::{thread, time::{Duration, Instant}};
+
+use alsa::{seq::{Addr, EvCtrl, EvNote, Event, EventType, PortCap, PortSubscribe, PortType, Seq}, Direction};
+use duvet::midi_input::MidiInput;
+use midly::MidiMessage;
+
+#[test]
+#[ignore]
+fn sequencer_loopback() {
+    let mut input = MidiInput::sequencer("duvet test", "duvet in").unwrap();
+    let destination = input.address().unwrap().unwrap();
+
+    // a second client standing in for a keyboard, wired to the input like `aconnect` would
+    let seq = Seq::open(None, Some(Direction::Playback), false).unwrap();
+    seq.set_client_name(c"duvet test keyboard").unwrap();
+    let port = seq.create_simple_port(c"out", PortCap::READ | PortCap::SUBS_READ, PortType::MIDI_GENERIC | PortType::APPLICATION).unwrap();
+    let subscription = PortSubscribe::empty().unwrap();
+    subscription.set_sender(Addr { client: seq.client_id().unwrap(), port });
+    subscription.set_dest(destination);
+    seq.subscribe_port(&subscription).unwrap();
+
+    let mut note_on = Event::new(EventType::Noteon, &EvNote { channel: 3, note: 60, velocity: 100, off_velocity: 0, duration: 0 });
+    let mut controller = Event::new(EventType::Controller, &EvCtrl { channel: 3, param: 7, value: 90 });
+    for event in [&mut note_on, &mut controller] {
+        event.set_source(port);
+        event.set_subs();
+        event.set_direct();
+        seq.event_output_direct(event).unwrap();
+    }
+
+    let mut received = vec![];
+    let start = Instant::now();
+    while received.len() < 2 && start.elapsed() < Duration::from_secs(1) {
+        input.poll(|channel, message| received.push((channel, message))).unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    assert_eq!(received, [
+        (3, MidiMessage::NoteOn { key: 60.into(), vel: 100.into() }),
+        (3, MidiMessage::Controller { controller: 7.into(), value: 90.into() }),
+    ]);
+}